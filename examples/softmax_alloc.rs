@@ -0,0 +1,45 @@
+//! Differentiates through a `softmax` returning a freshly allocated `Vec`: Enzyme allocates the
+//! shadow of the output with `__rust_alloc` as registered by `post`. Checks the gradient of a
+//! weighted sum of the output against the analytic one.
+//!
+//! `cargo enzyme --example softmax_alloc --run`
+use oxide_enzyme::autodiff;
+
+const N: usize = 4;
+
+#[inline(never)]
+fn softmax(input: &[f64; N]) -> Vec<f64>{
+    let sum: f64 = input.iter().map(|x| x.exp()).sum();
+    input.iter().map(|x| x.exp() / sum).collect()
+}
+
+#[inline(never)]
+fn weighted_sum(input: &[f64; N], weights: &[f64; N], result: &mut f64){
+    *result = softmax(input).iter().zip(weights).map(|(out, weight)| out * weight).sum();
+}
+
+fn main(){
+    let input = [0.5, -1., 2., 0.25];
+    let weights = [1., -2., 0.5, 3.];
+    let mut result = 0.;
+    let mut d_input = [0.; N];
+    let mut d_result = 1.;
+    autodiff!(weighted_sum, Duplicated(&input, &mut d_input), Const(&weights), Duplicated(&mut result, &mut d_result));
+
+    // d/dx_j sum_i w_i s_i = s_j (w_j - sum_i w_i s_i)
+    let output = softmax(&input);
+    let mean: f64 = output.iter().zip(&weights).map(|(out, weight)| out * weight).sum();
+    let mut mismatches = 0;
+    for j in 0..N{
+        let expected = output[j] * (weights[j] - mean);
+        let ok = (d_input[j] - expected).abs() < 1e-12;
+        if !ok{
+            mismatches += 1;
+        }
+        println!("d weighted_sum / d x_{}: {:>10.6} expected {:>10.6}{}", j, d_input[j], expected, if ok { "" } else { "  MISMATCH" });
+    }
+    if mismatches > 0{
+        eprintln!("{} entries of the gradient differ", mismatches);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,76 @@
+//! Checks the Jacobian of `softmax` computed column by column in forward mode against the one
+//! computed row by row in reverse mode, the one of a single vector forward mode call, and all
+//! against the analytic one.
+//!
+//! `cargo enzyme --example softmax_fwd_rev --run`
+use oxide_enzyme::{autodiff, fwddiff};
+
+const N: usize = 4;
+
+#[inline(never)]
+fn softmax(input: &[f64; N], output: &mut [f64; N]){
+    let sum: f64 = input.iter().map(|x| x.exp()).sum();
+    for (out, x) in output.iter_mut().zip(input){
+        *out = x.exp() / sum;
+    }
+}
+
+fn main(){
+    let input = [0.5, -1., 2., 0.25];
+    let mut output = [0.; N];
+
+    // forward mode: the tangent of the input e_j gives column j
+    let mut forward = [[0.; N]; N];
+    for j in 0..N{
+        let mut d_input = [0.; N];
+        d_input[j] = 1.;
+        let mut d_output = [0.; N];
+        fwddiff!(softmax, Duplicated(&input, &mut d_input), Duplicated(&mut output, &mut d_output));
+        for i in 0..N{
+            forward[i][j] = d_output[i];
+        }
+    }
+
+    // reverse mode: seeding the output with e_i gives row i
+    let mut reverse = [[0.; N]; N];
+    for (i, row) in reverse.iter_mut().enumerate(){
+        let mut d_input = [0.; N];
+        let mut d_output = [0.; N];
+        d_output[i] = 1.;
+        autodiff!(softmax, Duplicated(&input, &mut d_input), Duplicated(&mut output, &mut d_output));
+        *row = d_input;
+    }
+
+    // vector forward mode: all the columns from one call
+    let mut vector = [[0.; N]; N];
+    {
+        let [mut e_0, mut e_1, mut e_2, mut e_3] = [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]];
+        let [mut c_0, mut c_1, mut c_2, mut c_3] = [[0.; N]; N];
+        fwddiff!(softmax, width = 4,
+                 Duplicated(&input, &mut e_0, &mut e_1, &mut e_2, &mut e_3),
+                 Duplicated(&mut output, &mut c_0, &mut c_1, &mut c_2, &mut c_3));
+        for (j, column) in [c_0, c_1, c_2, c_3].iter().enumerate(){
+            for i in 0..N{
+                vector[i][j] = column[i];
+            }
+        }
+    }
+
+    softmax(&input, &mut output);
+    let mut mismatches = 0;
+    for i in 0..N{
+        for j in 0..N{
+            let expected = output[i] * (if i == j { 1. } else { 0. } - output[j]);
+            let ok = [forward[i][j], reverse[i][j], vector[i][j]].iter().all(|value| (value - expected).abs() < 1e-12);
+            if !ok{
+                mismatches += 1;
+            }
+            println!("d softmax_{} / d x_{}: forward {:>10.6} reverse {:>10.6} vector {:>10.6} expected {:>10.6}{}",
+                     i, j, forward[i][j], reverse[i][j], vector[i][j], expected, if ok { "" } else { "  MISMATCH" });
+        }
+    }
+    if mismatches > 0{
+        eprintln!("{} entries of the Jacobian differ", mismatches);
+        std::process::exit(1);
+    }
+}
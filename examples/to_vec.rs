@@ -0,0 +1,37 @@
+//! Differentiates through the `to_vec` of a slice, like `dummy_nn_with_loss` in src/main.rs: Enzyme
+//! needs the element type of the memcpy that copies it, which `ir-parser --inject-types` marks.
+//! Checks the gradient of a weighted sum of squares against the analytic one.
+//!
+//! `cargo enzyme --example to_vec --run`
+use oxide_enzyme::autodiff;
+
+const N: usize = 4;
+
+#[inline(never)]
+fn loss(input: &[f64; N], weights: &[f64; N], result: &mut f64){
+    let input_vec = input[..].to_vec();
+    *result = input_vec.iter().zip(weights).map(|(x, weight)| weight * x * x).sum();
+}
+
+fn main(){
+    let input = [0.5, -1., 2., 0.25];
+    let weights = [1., -2., 0.5, 3.];
+    let mut result = 0.;
+    let mut d_input = [0.; N];
+    let mut d_result = 1.;
+    autodiff!(loss, Duplicated(&input, &mut d_input), Const(&weights), Duplicated(&mut result, &mut d_result));
+
+    let mut mismatches = 0;
+    for j in 0..N{
+        let expected = 2. * weights[j] * input[j];
+        let ok = (d_input[j] - expected).abs() < 1e-12;
+        if !ok{
+            mismatches += 1;
+        }
+        println!("d loss / d x_{}: {:>10.6} expected {:>10.6}{}", j, d_input[j], expected, if ok { "" } else { "  MISMATCH" });
+    }
+    if mismatches > 0{
+        eprintln!("{} entries of the gradient differ", mismatches);
+        std::process::exit(1);
+    }
+}
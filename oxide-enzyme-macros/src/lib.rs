@@ -0,0 +1,185 @@
+//! `#[autodiff]`: generates the gradient function of the annotated function through `oxide_enzyme::autodiff!`
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, FnArg, Ident, ItemFn, Pat, Token, Type};
+
+/// `#[autodiff(grad_name, Activity, ...)]` with one activity per argument
+struct AutodiffArgs{
+    gradient_name: Ident,
+    activities: Vec<Ident>,
+}
+
+impl Parse for AutodiffArgs{
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut idents = Punctuated::<Ident, Token![,]>::parse_terminated(input)?.into_iter();
+        let gradient_name = idents.next()
+            .ok_or_else(|| syn::Error::new(Span::call_site(), "expected the name of the gradient function"))?;
+        Ok(AutodiffArgs{
+            gradient_name,
+            activities: idents.collect()
+        })
+    }
+}
+
+/// Generates `grad_name`, the reverse mode derivative of the annotated function.
+///
+/// ```ignore
+/// #[autodiff(grad_loss, Duplicated, Const, Active)]
+/// fn loss(weights: &Tensor, input: &Tensor, scale: f64) -> f64 { .. }
+/// ```
+///
+/// generates
+///
+/// ```ignore
+/// fn grad_loss(weights: &Tensor, d_weights: &mut Tensor, input: &Tensor, scale: f64) -> f64 { .. }
+/// ```
+///
+/// where a shadow argument follows every `Duplicated` or `DuplicatedNoNeed` one and the gradients of the `Active`
+/// arguments are returned. The annotated function is made `#[inline(never)]` so Enzyme still
+/// finds it in the IR, any other `#[inline]` on it is an error.
+#[proc_macro_attribute]
+pub fn autodiff(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as AutodiffArgs);
+    let function = parse_macro_input!(item as ItemFn);
+    match expand(args, function){
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(args: AutodiffArgs, mut function: ItemFn) -> syn::Result<proc_macro2::TokenStream>{
+    let signature = &function.sig;
+    if !signature.generics.params.is_empty(){
+        return Err(syn::Error::new(signature.generics.span(), "#[autodiff] does not support generic functions"));
+    }
+    if signature.inputs.len() != args.activities.len(){
+        return Err(syn::Error::new(
+            args.gradient_name.span(),
+            format!("`{}` takes {} arguments but {} activities were given", signature.ident, signature.inputs.len(), args.activities.len())
+        ));
+    }
+
+    let mut gradient_inputs = vec![];
+    let mut autodiff_args = vec![];
+    let mut active_count = 0;
+    for (input, activity) in signature.inputs.iter().zip(&args.activities){
+        let typed = match input{
+            FnArg::Typed(typed) => typed,
+            FnArg::Receiver(receiver) => return Err(syn::Error::new(receiver.span(), "#[autodiff] does not support methods")),
+        };
+        let name = match typed.pat.as_ref(){
+            Pat::Ident(pat) => &pat.ident,
+            other => return Err(syn::Error::new(other.span(), "#[autodiff] arguments must be plain identifiers")),
+        };
+        let ty = &typed.ty;
+        match activity.to_string().as_str(){
+            "Const" => {
+                gradient_inputs.push(quote!(#name: #ty));
+                autodiff_args.push(quote!(Const(#name)));
+            }
+            "Active" => {
+                active_count += 1;
+                gradient_inputs.push(quote!(#name: #ty));
+                autodiff_args.push(quote!(Active(#name)));
+            }
+            "Duplicated" | "DuplicatedNoNeed" => {
+                let reference = match ty.as_ref(){
+                    Type::Reference(reference) => reference,
+                    other => return Err(syn::Error::new(other.span(), "Duplicated arguments must be references such as `&Tensor`")),
+                };
+                let pointee = &reference.elem;
+                let shadow = format_ident!("d_{}", name);
+                gradient_inputs.push(quote!(#name: #ty));
+                gradient_inputs.push(quote!(#shadow: &mut #pointee));
+                let primal = match reference.mutability{
+                    Some(_) => quote!(&mut *#name),
+                    None => quote!(#name),
+                };
+                autodiff_args.push(quote!(#activity(#primal, #shadow)));
+            }
+            _ => return Err(syn::Error::new(activity.span(), "expected `Const`, `Duplicated`, `DuplicatedNoNeed` or `Active`")),
+        }
+    }
+
+    let gradients = match active_count{
+        0 => quote!(()),
+        1 => quote!(f64),
+        2 => quote!((f64, f64)),
+        _ => return Err(syn::Error::new(args.gradient_name.span(), "#[autodiff] supports at most two Active arguments")),
+    };
+    let visibility = &function.vis;
+    let target = &signature.ident;
+    let gradient_name = &args.gradient_name;
+    let doc = format!("Reverse mode derivative of [`{}`], generated by `#[autodiff]`", target);
+    let gradient = quote!{
+        #[doc = #doc]
+        #visibility fn #gradient_name(#(#gradient_inputs),*) -> #gradients {
+            ::oxide_enzyme::autodiff!(#target, #(#autodiff_args),*)
+        }
+    };
+    let inline_never: syn::Attribute = syn::parse_quote!(#[inline(never)]);
+    match function.attrs.iter().find(|attr| attr.path.is_ident("inline")){
+        Some(inline) if inline.tokens.to_string() == inline_never.tokens.to_string() => {}
+        Some(inline) => return Err(syn::Error::new(inline.span(), "#[autodiff] functions are #[inline(never)] so Enzyme finds them, remove this #[inline]")),
+        None => function.attrs.push(inline_never),
+    }
+    Ok(quote!{
+        #function
+        #gradient
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn expand_str(attr: &str, item: &str) -> Result<String, String>{
+        let args: AutodiffArgs = syn::parse_str(attr).unwrap();
+        let function: ItemFn = syn::parse_str(item).unwrap();
+        expand(args, function).map(|tokens| tokens.to_string()).map_err(|e| e.to_string())
+    }
+
+    fn tokens(source: &str) -> String{
+        source.parse::<proc_macro2::TokenStream>().unwrap().to_string()
+    }
+
+    #[test]
+    fn gradient_function(){
+        let expanded = expand_str(
+            "grad_loss, Duplicated, Const, Active",
+            "pub fn loss(weights: &mut [f64; 2], input: &[f64; 2], scale: f64) -> f64 { scale * weights[0] * input[0] }"
+        ).unwrap();
+        assert_eq!(expanded, tokens(r#"
+            #[inline(never)]
+            pub fn loss(weights: &mut [f64; 2], input: &[f64; 2], scale: f64) -> f64 { scale * weights[0] * input[0] }
+            #[doc = "Reverse mode derivative of [`loss`], generated by `#[autodiff]`"]
+            pub fn grad_loss(weights: &mut [f64; 2], d_weights: &mut [f64; 2], input: &[f64; 2], scale: f64) -> f64 {
+                ::oxide_enzyme::autodiff!(loss, Duplicated(&mut *weights, d_weights), Const(input), Active(scale))
+            }
+        "#));
+    }
+
+    #[test]
+    fn inline_never_is_not_repeated(){
+        let expanded = expand_str("grad_square, Active", "#[inline(never)] fn square(x: f64) -> f64 { x * x }").unwrap();
+        assert_eq!(expanded.matches("inline").count(), 1, "{}", expanded);
+        for inline in ["#[inline]", "#[inline(always)]"]{
+            let error = expand_str("grad_square, Active", &format!("{} fn square(x: f64) -> f64 {{ x * x }}", inline)).unwrap_err();
+            assert!(error.contains("remove this #[inline]"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn invalid_activities(){
+        let square = "fn square(x: f64) -> f64 { x * x }";
+        assert_eq!(expand_str("grad_square, Active, Const", square).unwrap_err(), "`square` takes 1 arguments but 2 activities were given");
+        assert_eq!(expand_str("grad_square, Mutable", square).unwrap_err(), "expected `Const`, `Duplicated`, `DuplicatedNoNeed` or `Active`");
+        assert_eq!(expand_str("grad_square, Duplicated", square).unwrap_err(), "Duplicated arguments must be references such as `&Tensor`");
+    }
+}
@@ -0,0 +1,119 @@
+//! Times the type recovery on synthetic IR of growing size against the lookup it replaced, it should
+//! grow linearly. Run with `cargo test --release --bin ir-parser -- --ignored --nocapture recovery_time`
+//!
+//! Each generated function has a local `Tensor` whose `Vec<f64>` member type is shared by all of them,
+//! and a few `!DILocation`s, so the metadata grows linearly with the number of functions.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+use crate::file_parser::{extract_llvm_multiple_tags_tag, extract_rust_metadata, LLVMIRMetadata};
+use crate::structs::{LLVMDebugTypeInformation, LLVMLocalTypeVariableDebugInfo};
+use crate::recover_variables;
+
+const FUNCTION_COUNTS: [usize; 4] = [1_000, 4_000, 16_000, 64_000];
+/// The quadratic baseline takes minutes past this
+const BASELINE_MAX_FUNCTIONS: usize = 4_000;
+const LOCATIONS_PER_FUNCTION: usize = 8;
+
+/// First ids of the shared nodes
+const FILE: usize = 1;
+const F64: usize = 2;
+const USIZE: usize = 3;
+const F64_POINTER: usize = 4;
+const VEC: usize = 5;
+const VEC_ELEMENTS: usize = 6;
+const VEC_MEMBERS: usize = 7;
+const FIRST_FUNCTION_NODE: usize = 10;
+const NODES_PER_FUNCTION: usize = 6 + LOCATIONS_PER_FUNCTION;
+
+fn synthetic_ir(function_count: usize) -> String{
+    let mut ir = String::new();
+    for function in 0..function_count{
+        let first = FIRST_FUNCTION_NODE + function * NODES_PER_FUNCTION;
+        writeln!(ir, "define void @f{}(ptr %tensor) !dbg !{} {{", function, first).unwrap();
+        writeln!(ir, "    #dbg_declare(ptr %tensor, !{}, !DIExpression(), !{})", first + 5, first + 6).unwrap();
+        writeln!(ir, "  ret void\n}}\n").unwrap();
+    }
+    writeln!(ir, "!{} = !DIFile(filename: \"src/main.rs\", directory: \"/synthetic\")", FILE).unwrap();
+    writeln!(ir, "!{} = !DIBasicType(name: \"f64\", size: 64, encoding: DW_ATE_float)", F64).unwrap();
+    writeln!(ir, "!{} = !DIBasicType(name: \"usize\", size: 64, encoding: DW_ATE_unsigned)", USIZE).unwrap();
+    writeln!(ir, "!{} = !DIDerivedType(tag: DW_TAG_pointer_type, name: \"*const f64\", baseType: !{}, size: 64, align: 64, dwarfAddressSpace: 0)", F64_POINTER, F64).unwrap();
+    writeln!(ir, "!{} = !DICompositeType(tag: DW_TAG_structure_type, name: \"Vec<f64, alloc::alloc::Global>\", file: !{}, size: 192, align: 64, elements: !{}, identifier: \"vec\")", VEC, FILE, VEC_ELEMENTS).unwrap();
+    writeln!(ir, "!{} = !{{!{}, !{}, !{}}}", VEC_ELEMENTS, VEC_MEMBERS, VEC_MEMBERS + 1, VEC_MEMBERS + 2).unwrap();
+    for (index, (name, base_type)) in [("ptr", F64_POINTER), ("cap", USIZE), ("len", USIZE)].iter().enumerate(){
+        writeln!(ir, "!{} = !DIDerivedType(tag: DW_TAG_member, name: \"{}\", scope: !{}, file: !{}, baseType: !{}, size: 64, align: 64, offset: {})", VEC_MEMBERS + index, name, VEC, FILE, base_type, index * 64).unwrap();
+    }
+    for function in 0..function_count{
+        let first = FIRST_FUNCTION_NODE + function * NODES_PER_FUNCTION;
+        writeln!(ir, "!{} = distinct !DISubprogram(name: \"f{}\", scope: !{}, file: !{}, line: {}, unit: null)", first, function, FILE, FILE, function).unwrap();
+        writeln!(ir, "!{} = !DICompositeType(tag: DW_TAG_structure_type, name: \"Tensor{}\", file: !{}, size: 256, align: 64, elements: !{}, identifier: \"tensor{}\")", first + 1, function, FILE, first + 2, function).unwrap();
+        writeln!(ir, "!{} = !{{!{}, !{}}}", first + 2, first + 3, first + 4).unwrap();
+        writeln!(ir, "!{} = !DIDerivedType(tag: DW_TAG_member, name: \"data\", scope: !{}, file: !{}, baseType: !{}, size: 192, align: 64, offset: 0)", first + 3, first + 1, FILE, VEC).unwrap();
+        writeln!(ir, "!{} = !DIDerivedType(tag: DW_TAG_member, name: \"scale\", scope: !{}, file: !{}, baseType: !{}, size: 64, align: 64, offset: 192)", first + 4, first + 1, FILE, F64).unwrap();
+        writeln!(ir, "!{} = !DILocalVariable(name: \"tensor\", arg: 1, scope: !{}, file: !{}, line: {}, type: !{})", first + 5, first, FILE, function, first + 1).unwrap();
+        for location in 0..LOCATIONS_PER_FUNCTION{
+            writeln!(ir, "!{} = !DILocation(line: {}, column: {}, scope: !{})", first + 6 + location, function, location, first).unwrap();
+        }
+    }
+    ir
+}
+
+/// The lookup before the nodes were indexed: each reference scans every node, and the type of each
+/// variable is walked again from scratch. Returns how many nodes were visited.
+fn linear_scan_recovery(nodes: &[LLVMDebugTypeInformation], tuples: &HashMap<String, Vec<String>>, variables: &[LLVMLocalTypeVariableDebugInfo]) -> usize{
+    let get_rust_debug_metadata = |id: &str| nodes.iter().find(|node| node.location_tag == id);
+    let mut visited = 0;
+    for variable in variables{
+        // the synthetic types have no cycles
+        let mut pending = vec![variable.location_tag.clone()];
+        while let Some(id) = pending.pop(){
+            let node = match get_rust_debug_metadata(&id){
+                Some(node) => node,
+                None => continue,
+            };
+            visited += 1;
+            pending.extend(["type", "baseType"].iter().filter_map(|field| node.parameters.get(*field).cloned()));
+            if let Some(elements) = node.parameters.get("elements").and_then(|elements| tuples.get(elements)){
+                pending.extend(elements.iter().cloned());
+            }
+        }
+    }
+    visited
+}
+
+#[test]
+#[ignore]
+fn recovery_time_grows_linearly(){
+    println!("{:>10} {:>12} {:>10} {:>10} {:>10} {:>8}", "functions", "nodes", "extract", "recover", "baseline", "speedup");
+    for &function_count in &FUNCTION_COUNTS{
+        let ir = synthetic_ir(function_count);
+        let start = Instant::now();
+        let metadata = LLVMIRMetadata::new(&ir).unwrap();
+        let extracted = Instant::now();
+        let variables = recover_variables(&metadata);
+        let recovered = Instant::now();
+        assert_eq!(variables.len(), function_count);
+        let nodes = FIRST_FUNCTION_NODE + function_count * NODES_PER_FUNCTION;
+        let recovery = recovered - start;
+
+        let baseline = if function_count <= BASELINE_MAX_FUNCTIONS{
+            let start = Instant::now();
+            let (nodes, _) = extract_rust_metadata(&ir);
+            let tuples = extract_llvm_multiple_tags_tag(&ir);
+            let visited = linear_scan_recovery(&nodes, &tuples, &metadata.llvm_local_type_variable_debug_info);
+            assert!(visited >= function_count);
+            Some(start.elapsed())
+        }else{
+            None
+        };
+        let (baseline_column, speedup_column) = match baseline{
+            Some(baseline) => (format!("{:.2}s", baseline.as_secs_f64()), format!("{:.1}x", baseline.as_secs_f64() / recovery.as_secs_f64())),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        println!("{:>10} {:>12} {:>9.2}s {:>9.2}s {:>10} {:>8}", function_count, nodes,
+                 (extracted - start).as_secs_f64(), (recovered - extracted).as_secs_f64(), baseline_column, speedup_column);
+        if function_count == BASELINE_MAX_FUNCTIONS{
+            assert!(baseline.unwrap() > recovery.max(Duration::from_millis(1)) * 2, "no speedup over the linear scan");
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! `cargo enzyme`: builds a binary of any crate with its `__enzyme_autodiff` and `__enzyme_fwddiff`
+//! calls differentiated
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::driver::{Project, TargetKind, Tools};
+use crate::platform::Platform;
+
+mod driver;
+mod platform;
+
+const USAGE: &str = "\
+Builds a binary with its `__enzyme_autodiff` and `__enzyme_fwddiff` calls differentiated by Enzyme
+
+Usage: cargo enzyme [OPTIONS] [-- ARGS]
+
+    --bin NAME              Binary to build, needed if the workspace has more than one
+    --example NAME          Example to build instead of a binary
+    --manifest-path PATH    Cargo.toml of the crate, defaults to the one of the current directory
+    --opt PATH              LLVM `opt` at least as recent as the LLVM of rustc, or $ENZYME_OPT,
+                            defaults to `opt`
+    --clang PATH            Compiler used to link, or $ENZYME_CLANG, defaults to `clang`
+    --plugin PATH           Enzyme LLVM plugin, or $ENZYME_PLUGIN, defaults to `LLVMEnzyme-<LLVM
+                            version of opt>.so` (`.dylib` on MacOS) in the current directory
+    --run                   Run the executable once linked, with ARGS
+";
+
+struct Options{
+    manifest_path: Option<PathBuf>,
+    kind: TargetKind,
+    bin: Option<String>,
+    tools: Tools,
+    run: bool,
+    run_args: Vec<String>,
+    help: bool,
+}
+
+fn tool_from_env(variable: &str, default: &str) -> PathBuf{
+    std::env::var_os(variable).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(default))
+}
+
+fn parse_options(args: impl Iterator<Item = String>) -> Result<Options, String>{
+    let mut options = Options{
+        manifest_path: None,
+        kind: TargetKind::Bin,
+        bin: None,
+        tools: Tools{
+            opt: tool_from_env("ENZYME_OPT", "opt"),
+            clang: tool_from_env("ENZYME_CLANG", "clang"),
+            enzyme_plugin: std::env::var_os("ENZYME_PLUGIN").map(PathBuf::from),
+        },
+        run: false,
+        run_args: vec![],
+        help: false
+    };
+    // cargo calls us as `cargo-enzyme enzyme ARGS`
+    let mut args = args.skip(1).skip_while(|arg| arg == "enzyme");
+    while let Some(arg) = args.next(){
+        let mut value = || args.next().ok_or_else(|| format!("`{}` expects a value", arg));
+        match arg.as_str(){
+            "-h" | "--help" => options.help = true,
+            "--bin" => {
+                options.kind = TargetKind::Bin;
+                options.bin = Some(value()?);
+            }
+            "--example" => {
+                options.kind = TargetKind::Example;
+                options.bin = Some(value()?);
+            }
+            "--manifest-path" => options.manifest_path = Some(value()?.into()),
+            "--opt" => options.tools.opt = value()?.into(),
+            "--clang" => options.tools.clang = value()?.into(),
+            "--plugin" => options.tools.enzyme_plugin = Some(value()?.into()),
+            "--run" => options.run = true,
+            "--" => {
+                options.run_args = args.by_ref().collect();
+            }
+            _ => return Err(format!("unknown argument `{}`", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<i32, String>{
+    let project = Project::resolve(options.manifest_path.as_deref(), options.kind, options.bin.as_deref())?;
+    let platform = Platform::for_triple(&driver::host_triple(&project)?)?;
+
+    eprintln!("{:>12} LLVM IR of `{}`", "Emitting", project.bin);
+    let (ir, link_capture) = driver::emit_ir(&project)?;
+    eprintln!("{:>12} activity markers", "Rewriting");
+    let ir = driver::rewrite_markers(&project, &ir)?;
+    eprintln!("{:>12} arguments of the differentiated functions", "Typing");
+    let ir = driver::inject_types(&project, &ir)?;
+    eprintln!("{:>12} with Enzyme", "Deriving");
+    let ir = driver::run_enzyme(&project, &options.tools, &platform, &ir)?;
+    eprintln!("{:>12} {}", "Linking", project.executable().display());
+    let executable = driver::link(&project, &options.tools, &platform, &ir, link_capture.as_deref())?;
+
+    if !options.run{
+        return Ok(0);
+    }
+    eprintln!("{:>12} `{}`", "Running", executable.display());
+    let status = Command::new(&executable)
+        .args(&options.run_args)
+        .status()
+        .map_err(|e| format!("could not run `{}`: {}", executable.display(), e))?;
+    Ok(status.code().unwrap_or(1))
+}
+
+fn main(){
+    if let Some(capture) = std::env::var_os(driver::LINK_CAPTURE_VAR){
+        // rustc is running us as its linker from `driver::emit_ir`
+        if let Err(e) = driver::capture_link(Path::new(&capture), std::env::args().skip(1).collect()){
+            eprintln!("error: cargo-enzyme could not capture the link arguments: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let result = parse_options(std::env::args()).and_then(|options| {
+        if options.help{
+            print!("{}", USAGE);
+            Ok(0)
+        }else{
+            run(&options)
+        }
+    });
+    match result{
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Command line handling shared by the `post` and `ir-parser` binaries
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// Where to read the IR from or write it to, `-` on the command line means stdin/stdout
+#[derive(Debug, Clone)]
+pub enum Stream{
+    Std,
+    File(PathBuf),
+}
+
+impl Stream{
+    fn from_arg(arg: &str) -> Self{
+        if arg == "-"{
+            Stream::Std
+        }else{
+            Stream::File(PathBuf::from(arg))
+        }
+    }
+
+    pub fn display_name(&self) -> String{
+        match self{
+            Stream::Std => "<stdio>".to_string(),
+            Stream::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Args{
+    pub input: Stream,
+    pub output: Stream,
+    /// The flags given among those the binary accepts, such as `--check`
+    pub flags: Vec<&'static str>,
+    /// The options given among those the binary accepts with their value, such as `--inactive FILE`
+    pub options: Vec<(&'static str, String)>,
+    pub help: bool,
+}
+
+impl Args{
+    pub fn flag(&self, flag: &str) -> bool{
+        self.flags.contains(&flag)
+    }
+}
+
+/// Parses `[INPUT] [-o OUTPUT]`, any of `flags` and of `options` followed by their value, both input
+/// and output default to stdin/stdout
+pub fn parse_args(args: impl Iterator<Item = String>, flags: &[&'static str], options: &[&'static str]) -> Result<Args, String>{
+    let mut parsed = Args{
+        input: Stream::Std,
+        output: Stream::Std,
+        flags: vec![],
+        options: vec![],
+        help: false
+    };
+    let mut input = None;
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "-h" | "--help" => parsed.help = true,
+            flag if flags.contains(&flag) => parsed.flags.extend(flags.iter().find(|known| **known == flag)),
+            option if options.contains(&option) => {
+                let value = args.next().ok_or_else(|| format!("`{}` expects a value", arg))?;
+                parsed.options.extend(options.iter().find(|known| **known == option).map(|option| (*option, value)));
+            }
+            "-o" | "--output" => {
+                let output = args.next().ok_or_else(|| format!("`{}` expects a file name", arg))?;
+                parsed.output = Stream::from_arg(&output);
+            }
+            flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option `{}`", flag)),
+            path => {
+                if input.is_some(){
+                    return Err(format!("unexpected extra input `{}`", path));
+                }
+                input = Some(Stream::from_arg(path));
+            }
+        }
+    }
+    if let Some(input) = input{
+        parsed.input = input;
+    }
+    Ok(parsed)
+}
+
+pub fn read_input(input: &Stream) -> Result<String, String>{
+    let mut contents = String::new();
+    let result = match input{
+        Stream::Std => std::io::stdin().read_to_string(&mut contents),
+        Stream::File(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut contents)),
+    };
+    result.map_err(|e| format!("could not read `{}`: {}", input.display_name(), e))?;
+    Ok(contents)
+}
+
+pub fn write_output(output: &Stream, contents: &str) -> Result<(), String>{
+    let result = match output{
+        Stream::Std => std::io::stdout().lock().write_all(contents.as_bytes()),
+        Stream::File(path) => std::fs::File::create(path).and_then(|mut file| file.write_all(contents.as_bytes())),
+    };
+    result.map_err(|e| format!("could not write `{}`: {}", output.display_name(), e))
+}
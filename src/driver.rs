@@ -0,0 +1,453 @@
+//! The steps run by `cargo enzyme`: emit the crate IR, rewrite its activity markers with `post`,
+//! type the arguments of the differentiated functions with `ir-parser`, differentiate it with Enzyme through `opt` and link the result the way rustc would have
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use serde::Deserialize;
+use crate::platform::Platform;
+
+/// External programs used by the pipeline
+#[derive(Debug, Clone)]
+pub struct Tools{
+    pub opt: PathBuf,
+    pub clang: PathBuf,
+    /// Defaults to [`Platform::enzyme_plugin`] for the LLVM version of `opt`, in the working directory
+    pub enzyme_plugin: Option<PathBuf>,
+}
+
+/// Cargo targets `cargo enzyme` can build
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetKind{
+    Bin,
+    Example,
+}
+
+impl TargetKind{
+    /// Kind as written by `cargo metadata` and in the `--bin`/`--example` flags
+    pub fn as_str(self) -> &'static str{
+        match self{
+            TargetKind::Bin => "bin",
+            TargetKind::Example => "example",
+        }
+    }
+}
+
+/// The binary or example we differentiate and where its intermediate files go
+#[derive(Debug, Clone)]
+pub struct Project{
+    pub manifest_path: PathBuf,
+    pub package: String,
+    pub kind: TargetKind,
+    pub bin: String,
+    /// The `required-features` of the target, enabled when emitting its IR
+    pub features: Vec<String>,
+    pub target_dir: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata{
+    packages: Vec<CargoPackage>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage{
+    name: String,
+    manifest_path: PathBuf,
+    targets: Vec<CargoTarget>,
+}
+
+#[derive(Deserialize)]
+struct CargoTarget{
+    name: String,
+    kind: Vec<String>,
+    #[serde(default, rename = "required-features")]
+    required_features: Vec<String>,
+}
+
+impl Project{
+    /// Finds the `kind` target named `bin` in the workspace of `manifest_path`, `bin` can be omitted
+    /// if there is a single target of that kind
+    pub fn resolve(manifest_path: Option<&Path>, kind: TargetKind, bin: Option<&str>) -> Result<Self, String>{
+        let mut cargo = Command::new("cargo");
+        cargo.args(["metadata", "--no-deps", "--format-version", "1"]);
+        if let Some(manifest_path) = manifest_path{
+            cargo.arg("--manifest-path").arg(manifest_path);
+        }
+        let output = cargo.output().map_err(|e| format!("could not run `cargo metadata`: {}", e))?;
+        if !output.status.success(){
+            return Err(format!("`cargo metadata` failed:\n{}", String::from_utf8_lossy(&output.stderr)));
+        }
+        let metadata: CargoMetadata = serde_json::from_slice(&output.stdout)
+            .map_err(|e| format!("could not understand `cargo metadata` output: {}", e))?;
+
+        let bins: Vec<(&CargoPackage, &CargoTarget)> = metadata.packages.iter()
+            .flat_map(|package| package.targets.iter().map(move |target| (package, target)))
+            .filter(|(_, target)| target.kind.iter().any(|target_kind| target_kind == kind.as_str()))
+            .filter(|(_, target)| bin.is_none_or(|bin| bin == target.name))
+            .collect();
+        let (package, target) = match (bins.as_slice(), bin){
+            ([found], _) => *found,
+            ([], Some(bin)) => return Err(format!("no {} named `{}` in the workspace", kind.as_str(), bin)),
+            ([], None) => return Err(format!("the workspace has no {} to differentiate", kind.as_str())),
+            (_, _) => {
+                let names: Vec<&str> = bins.iter().map(|(_, target)| target.name.as_str()).collect();
+                return Err(format!("more than one {0}, pick one with `--{0}`: {1}", kind.as_str(), names.join(", ")));
+            }
+        };
+        Ok(Project{
+            manifest_path: package.manifest_path.clone(),
+            package: package.name.clone(),
+            kind,
+            bin: target.name.clone(),
+            features: target.required_features.clone(),
+            target_dir: metadata.target_directory,
+        })
+    }
+
+    /// Directory holding the intermediate IR files and the final executable
+    pub fn work_dir(&self) -> PathBuf{
+        self.target_dir.join("enzyme")
+    }
+
+    fn work_file(&self, suffix: &str) -> PathBuf{
+        self.work_dir().join(format!("{}{}", self.crate_name(), suffix))
+    }
+
+    /// Name rustc gives the crate of the binary
+    pub fn crate_name(&self) -> String{
+        self.bin.replace('-', "_")
+    }
+
+    pub fn executable(&self) -> PathBuf{
+        self.work_file(std::env::consts::EXE_SUFFIX)
+    }
+}
+
+/// Runs `command`, turning a failure to start it or a non zero exit into an error naming `step`
+fn run_step(step: &str, command: &mut Command) -> Result<(), String>{
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command.status()
+        .map_err(|e| format!("{}: could not run `{}`: {}", step, program, e))?;
+    if status.success(){
+        Ok(())
+    }else{
+        Err(format!("{}: `{}` failed with {}", step, program, status))
+    }
+}
+
+fn host_rustc(project: &Project, arg: &str) -> Result<String, String>{
+    let manifest_dir = project.manifest_path.parent().unwrap_or_else(|| Path::new("."));
+    let output = Command::new("rustc")
+        .arg(arg)
+        .current_dir(manifest_dir)
+        .output()
+        .map_err(|e| format!("could not run `rustc {}`: {}", arg, e))?;
+    if !output.status.success(){
+        return Err(format!("`rustc {}` failed:\n{}", arg, String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Triple of the toolchain used by the project, as reported by `rustc -vV`
+pub fn host_triple(project: &Project) -> Result<String, String>{
+    host_rustc(project, "-vV")?
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .ok_or_else(|| "`rustc -vV` did not report the host triple".to_string())
+}
+
+/// Major version in the output of `opt --version` ("Ubuntu LLVM version 19.1.1") or `rustc -vV` ("LLVM version: 19.1.7")
+pub fn llvm_major_version(version: &str) -> Option<u32>{
+    let (_, rest) = version.split_once("LLVM version")?;
+    let number = rest.trim_start_matches(':').split_whitespace().next()?;
+    number.split('.').next()?.parse().ok()
+}
+
+/// LLVM major version `opt` was built from
+fn opt_llvm_major(tools: &Tools) -> Result<u32, String>{
+    let output = Command::new(&tools.opt)
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("running Enzyme: could not run `{}`: {}", tools.opt.display(), e))?;
+    llvm_major_version(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| format!("running Enzyme: `{} --version` did not report an LLVM version", tools.opt.display()))
+}
+
+/// Environment variable telling `cargo-enzyme` that rustc runs it as the linker, holding the file
+/// where the link arguments should be saved
+pub const LINK_CAPTURE_VAR: &str = "CARGO_ENZYME_LINK_CAPTURE";
+
+/// Emits the IR of the whole binary crate as a single module. rustc links through `cargo-enzyme`
+/// itself, which records the link arguments (see [`capture_link`]) instead of linking the
+/// not yet differentiated code. Returns the IR and the captured arguments, if rustc linked.
+pub fn emit_ir(project: &Project) -> Result<(PathBuf, Option<PathBuf>), String>{
+    let ir = project.work_file(".ll");
+    let capture = project.work_file("_link.json");
+    std::fs::create_dir_all(project.work_dir())
+        .map_err(|e| format!("could not create `{}`: {}", project.work_dir().display(), e))?;
+    for stale in [&ir, &capture]{
+        if stale.exists(){
+            std::fs::remove_file(stale).map_err(|e| format!("could not remove stale `{}`: {}", stale.display(), e))?;
+        }
+    }
+    let this_exe = std::env::current_exe()
+        .map_err(|e| format!("emitting IR: could not locate `cargo-enzyme`: {}", e))?;
+    let mut emit_arg = OsString::from("--emit=llvm-ir=");
+    emit_arg.push(&ir);
+    let mut linker_arg = OsString::from("linker=");
+    linker_arg.push(this_exe);
+    let mut cargo = Command::new("cargo");
+    cargo.args(["rustc", "--release", "--manifest-path"])
+        .arg(&project.manifest_path)
+        .args(["--package", &project.package])
+        .arg(format!("--{}", project.kind.as_str()))
+        .arg(&project.bin);
+    if !project.features.is_empty(){
+        cargo.args(["--features", &project.features.join(",")]);
+    }
+    let status = cargo.arg("--")
+        .arg(emit_arg)
+        // the debug info is where `inject_types` recovers the Rust types from
+        .args(["-C", "codegen-units=1", "-C", "lto=off", "-C", "debuginfo=2", "-C"])
+        .arg(linker_arg)
+        .env(LINK_CAPTURE_VAR, &capture)
+        .status()
+        .map_err(|e| format!("emitting IR: could not run `cargo rustc`: {}", e))?;
+    if !ir.exists(){
+        return Err(format!("emitting IR: compilation of `{}` failed ({})", project.bin, status));
+    }
+    if !capture.exists(){
+        eprintln!("warning: rustc did not link `{}` ({}), linking it without its dependencies", project.bin, status);
+        return Ok((ir, None));
+    }
+    Ok((ir, Some(capture)))
+}
+
+/// Expands the `@file` arguments rustc uses when the command line gets too long
+fn expand_response_files(args: Vec<String>) -> Result<Vec<String>, String>{
+    let mut expanded = vec![];
+    for arg in args{
+        match arg.strip_prefix('@'){
+            Some(file) => {
+                let contents = std::fs::read_to_string(file)
+                    .map_err(|e| format!("could not read response file `{}`: {}", file, e))?;
+                expanded.extend(contents.lines().map(str::to_string));
+            }
+            None => expanded.push(arg),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Linker side of [`emit_ir`]: saves the arguments rustc links the crate with into `capture` as JSON.
+/// Object files are copied next to it since rustc deletes its temporary ones once linking is done.
+pub fn capture_link(capture: &Path, args: Vec<String>) -> Result<(), String>{
+    let objects_dir = capture.with_extension("objects");
+    std::fs::create_dir_all(&objects_dir)
+        .map_err(|e| format!("could not create `{}`: {}", objects_dir.display(), e))?;
+    let mut captured = vec![];
+    for arg in expand_response_files(args)?{
+        let path = Path::new(&arg);
+        let is_object = path.extension().is_some_and(|extension| extension == "o") && path.is_file();
+        match path.file_name().filter(|_| is_object){
+            Some(file_name) => {
+                let copy = objects_dir.join(file_name);
+                std::fs::copy(path, &copy).map_err(|e| format!("could not copy `{}`: {}", arg, e))?;
+                captured.push(copy.display().to_string());
+            }
+            None => captured.push(arg),
+        }
+    }
+    let json = serde_json::to_string_pretty(&captured).map_err(|e| e.to_string())?;
+    std::fs::write(capture, json).map_err(|e| format!("could not write `{}`: {}", capture.display(), e))
+}
+
+/// Functions the project wants Enzyme to treat as inactive, next to its `Cargo.toml`
+pub const INACTIVE_CONFIG: &str = "enzyme-inactive.txt";
+
+/// Runs the `post` binary installed next to this one over `ir`, passing it the project's
+/// [`INACTIVE_CONFIG`] if there is one
+pub fn rewrite_markers(project: &Project, ir: &Path) -> Result<PathBuf, String>{
+    let post = std::env::current_exe()
+        .map_err(|e| format!("rewriting markers: could not locate `post`: {}", e))?
+        .with_file_name(format!("post{}", std::env::consts::EXE_SUFFIX));
+    if !post.exists(){
+        return Err(format!("rewriting markers: `{}` not found, install it together with `cargo-enzyme`", post.display()));
+    }
+    let replaced = project.work_file("_replaced.ll");
+    let mut command = Command::new(post);
+    command.arg(ir).arg("-o").arg(&replaced);
+    let inactive = project.manifest_path.with_file_name(INACTIVE_CONFIG);
+    if inactive.exists(){
+        command.arg("--inactive").arg(inactive);
+    }
+    run_step("rewriting markers", &mut command)?;
+    Ok(replaced)
+}
+
+/// Runs the `ir-parser` binary installed next to this one over `ir`, giving the parameters of the
+/// differentiated functions the TypeTrees of their Rust types
+pub fn inject_types(project: &Project, ir: &Path) -> Result<PathBuf, String>{
+    let ir_parser = std::env::current_exe()
+        .map_err(|e| format!("injecting types: could not locate `ir-parser`: {}", e))?
+        .with_file_name(format!("ir-parser{}", std::env::consts::EXE_SUFFIX));
+    if !ir_parser.exists(){
+        return Err(format!("injecting types: `{}` not found, install it together with `cargo-enzyme`", ir_parser.display()));
+    }
+    let typed = project.work_file("_typed.ll");
+    run_step("injecting types", Command::new(ir_parser).arg("--inject-types").arg(ir).arg("-o").arg(&typed))?;
+    Ok(typed)
+}
+
+/// Differentiates the module with the Enzyme `opt` plugin. `opt` has to read the IR of the LLVM rustc
+/// was built with, and the plugin has to be the one built for `opt`
+pub fn run_enzyme(project: &Project, tools: &Tools, platform: &Platform, ir: &Path) -> Result<PathBuf, String>{
+    let opt_major = opt_llvm_major(tools)?;
+    if let Some(rustc_major) = llvm_major_version(&host_rustc(project, "-vV")?){
+        if opt_major < rustc_major{
+            return Err(format!("running Enzyme: `{}` is LLVM {} but rustc emits LLVM {} IR, pass a newer `opt` with `--opt`",
+                tools.opt.display(), opt_major, rustc_major));
+        }
+    }
+    let plugin = tools.enzyme_plugin.clone().unwrap_or_else(|| PathBuf::from(platform.enzyme_plugin(opt_major)));
+    if !plugin.exists(){
+        return Err(format!("running Enzyme: plugin `{}` not found, pass its path with `--plugin`", plugin.display()));
+    }
+    // `-load` registers the Enzyme options such as `-enzyme-rust-type`, `-load-pass-plugin` the pass itself
+    let mut load_arg = OsString::from("-load=");
+    load_arg.push(&plugin);
+    let mut pass_plugin_arg = OsString::from("-load-pass-plugin=");
+    pass_plugin_arg.push(&plugin);
+    let differentiated = project.work_file("_final.ll");
+    run_step("running Enzyme", Command::new(&tools.opt)
+        .arg(ir)
+        .arg(load_arg)
+        .arg(pass_plugin_arg)
+        .args(["-passes=enzyme", "-enzyme-rust-type", "-S", "-o"])
+        .arg(&differentiated))?;
+    Ok(differentiated)
+}
+
+/// Turns the link arguments captured from rustc into ours: the objects rustc compiled from the crate
+/// itself are replaced by the differentiated `ir` and the output by `output`. Everything else, such as
+/// the rlibs of the dependencies and the native libraries they need, is kept as is.
+pub fn swap_crate_objects(captured: &[String], crate_name: &str, ir: &Path, output: &Path) -> Vec<OsString>{
+    let crate_object_prefix = format!("{}-", crate_name);
+    let is_crate_object = |arg: &str| {
+        Path::new(arg).file_name()
+            .map(|name| name.to_string_lossy())
+            .is_some_and(|name| name.starts_with(&crate_object_prefix) && name.contains("-cgu.") && name.ends_with(".o"))
+    };
+    let mut args = vec![];
+    let mut ir_added = false;
+    let mut captured = captured.iter();
+    while let Some(arg) = captured.next(){
+        if arg == "-o"{
+            captured.next();
+            args.push("-o".into());
+            args.push(output.into());
+        }else if is_crate_object(arg){
+            if !ir_added{
+                args.push(ir.into());
+                ir_added = true;
+            }
+        }else{
+            args.push(arg.into());
+        }
+    }
+    args
+}
+
+/// Arguments given to clang to link `ir` into `output`: the captured ones as rewritten by
+/// [`swap_crate_objects`], which already hold the flags and system libraries rustc links with, or
+/// the defaults of the platform when rustc did not link
+pub fn link_args(platform: &Platform, captured: Option<&[String]>, crate_name: &str, ir: &Path, output: &Path) -> Vec<OsString>{
+    if let Some(captured) = captured{
+        return swap_crate_objects(captured, crate_name, ir, output);
+    }
+    let mut args: Vec<OsString> = platform.link_flags.iter().map(OsString::from).collect();
+    args.extend([ir.into(), "-o".into(), output.into()]);
+    args.extend(platform.system_libs.iter().map(OsString::from));
+    args
+}
+
+pub fn link(project: &Project, tools: &Tools, platform: &Platform, ir: &Path, capture: Option<&Path>) -> Result<PathBuf, String>{
+    let captured: Option<Vec<String>> = match capture{
+        Some(capture) => {
+            let captured = std::fs::read_to_string(capture)
+                .map_err(|e| format!("linking: could not read `{}`: {}", capture.display(), e))?;
+            Some(serde_json::from_str(&captured)
+                .map_err(|e| format!("linking: invalid link arguments in `{}`: {}", capture.display(), e))?)
+        }
+        None => None,
+    };
+    let executable = project.executable();
+    let args = link_args(platform, captured.as_deref(), &project.crate_name(), ir, &executable);
+    run_step("linking", Command::new(&tools.clang).args(args))?;
+    Ok(executable)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn linux_link_command(){
+        let platform = Platform::for_triple("x86_64-unknown-linux-gnu").unwrap();
+        let std_lib = "/home/user/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/lib/rustlib/x86_64-unknown-linux-gnu/lib";
+        let captured: Vec<String> = vec![
+            "-m64".to_string(),
+            "target/enzyme/model_link.objects/symbols.o".to_string(),
+            "target/enzyme/model_link.objects/model-6f1c.model.2a4b-cgu.0.rcgu.o".to_string(),
+            "target/enzyme/model_link.objects/model-6f1c.3kq9x.rcgu.o".to_string(),
+            "-Wl,-Bstatic".to_string(),
+            "target/release/deps/libserde_json-91ab.rlib".to_string(),
+            format!("{}/libstd-3c4d.rlib", std_lib),
+            "-Wl,-Bdynamic".to_string(),
+            "-lgcc_s".to_string(),
+            "-o".to_string(),
+            "target/release/deps/model-6f1c".to_string(),
+            "-nodefaultlibs".to_string(),
+        ];
+
+        let ir = Path::new("target/enzyme/model_final.ll");
+        let output = Path::new("target/enzyme/model");
+        let args = link_args(&platform, Some(&captured), "model", ir, output);
+
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(args, vec![
+            "-m64".to_string(),
+            "target/enzyme/model_link.objects/symbols.o".to_string(),
+            "target/enzyme/model_final.ll".to_string(),
+            "target/enzyme/model_link.objects/model-6f1c.3kq9x.rcgu.o".to_string(),
+            "-Wl,-Bstatic".to_string(),
+            "target/release/deps/libserde_json-91ab.rlib".to_string(),
+            format!("{}/libstd-3c4d.rlib", std_lib),
+            "-Wl,-Bdynamic".to_string(),
+            "-lgcc_s".to_string(),
+            "-o".to_string(),
+            "target/enzyme/model".to_string(),
+            "-nodefaultlibs".to_string(),
+        ]);
+
+        let args = link_args(&platform, None, "model", ir, output);
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(args, [
+            "-m64", "-Wl,--gc-sections", "-nodefaultlibs",
+            "target/enzyme/model_final.ll", "-o", "target/enzyme/model",
+            "-lc", "-lm", "-ldl", "-lpthread", "-lgcc_s",
+        ]);
+    }
+
+    #[test]
+    fn plugin_follows_the_llvm_version(){
+        assert_eq!(llvm_major_version("Debian LLVM version 14.0.6\n  Optimized build.\n  Default target: x86_64-pc-linux-gnu"), Some(14));
+        assert_eq!(llvm_major_version("binary: rustc\nhost: x86_64-unknown-linux-gnu\nLLVM version: 19.1.7"), Some(19));
+        assert_eq!(llvm_major_version("rustc 1.95.0"), None);
+        let platform = Platform::for_triple("x86_64-unknown-linux-gnu").unwrap();
+        assert_eq!(platform.enzyme_plugin(19), "LLVMEnzyme-19.so");
+        assert_eq!(Platform::for_triple("x86_64-apple-darwin").unwrap().enzyme_plugin(14), "LLVMEnzyme-14.dylib");
+    }
+}
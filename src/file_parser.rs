@@ -1,36 +1,109 @@
-use crate::structs::{LLVMLocalTypeVariableDebugInfo, LLVMDebugTypeInformation};
+use crate::structs::{LLVMLocalTypeVariableDebugInfo, LLVMDebugTypeInformation, DebugIntrinsic, DIExpression, DifferentiatedFunction};
+use crate::metadata_fields::parse_fields;
+use crate::metadata_store::MetadataStore;
+use std::convert::TryFrom;
 use regex::Regex;
-use std::io::Read;
-use crate::{LLVMIRMetadata, extract_llvm_multiple_tags, get_all_params};
 use std::collections::HashMap;
 
+pub struct LLVMIRMetadata{
+    pub llvm_local_type_variable_debug_info: Vec<LLVMLocalTypeVariableDebugInfo>,
+    pub metadata: MetadataStore,
+    pub differentiated_functions: Vec<DifferentiatedFunction>,
+    /// Nodes left out because their fields could not be read
+    pub warnings: Vec<String>,
+}
+
 impl LLVMIRMetadata{
-    pub fn new(file_path: &str) -> Self{
-        let mut file_as_string = String::new();
-        let mut file = std::fs::File::open(file_path).unwrap();
-        file.read_to_string(&mut file_as_string).unwrap();
-        let llvm_to_rust_metadata_link = extract_llvm_to_rust_metadata(&file_as_string);
-        let rust_metadata = extract_rust_metadata(&file_as_string);
-        let multi_tags_tag = extract_llvm_multiple_tags_tag(&file_as_string);
-        Self{
-            multiple_tags_tag: multi_tags_tag,
+    pub fn new(file_as_string: &str) -> Result<Self, String>{
+        let llvm_to_rust_metadata_link = extract_llvm_to_rust_metadata(file_as_string)?;
+        let (rust_metadata, warnings) = extract_rust_metadata(file_as_string);
+        let multi_tags_tag = extract_llvm_multiple_tags_tag(file_as_string);
+        Ok(Self{
             llvm_local_type_variable_debug_info: llvm_to_rust_metadata_link,
-            llvm_debug_type_information: rust_metadata
+            metadata: MetadataStore::new(rust_metadata, multi_tags_tag),
+            differentiated_functions: extract_differentiated_functions(file_as_string),
+            warnings
+        })
+    }
+}
+
+/// The first argument of the argument list `args`, which starts after the `(` of a call
+fn first_argument(args: &str) -> &str{
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    for (index, c) in args.char_indices(){
+        match c{
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' | ']' | '}' | '>' if depth > 0 => depth -= 1,
+            ',' | ')' if depth == 0 => return &args[..index],
+            _ => {}
         }
     }
+    args
+}
+
+/// Finds the functions given to Enzyme by calls such as
+/// `call double (i64, ...) @__enzyme_autodiff(i64 ptrtoint (ptr @loss to i64), ...)`, each once, with
+/// the `DISubprogram` of their `define`
+pub fn extract_differentiated_functions(file_as_string: &str) -> Vec<DifferentiatedFunction>{
+    let name_regex = Regex::new(r#"@("[^"]+"|[-\w.$]+)"#).unwrap();
+    let define_regex = Regex::new(r#"(?m)^define [^@]*@("[^"]+"|[-\w.$]+)\(.*!dbg (!\d+)"#).unwrap();
+    let mut names: Vec<String> = vec![];
+    for entry_point in ["@__enzyme_autodiff(", "@__enzyme_fwddiff("]{
+        for (position, _) in file_as_string.match_indices(entry_point){
+            let args = &file_as_string[position + entry_point.len()..];
+            // a function pointer held in a register can't be followed
+            if let Some(caps) = name_regex.captures(first_argument(args)){
+                let name = format!("@{}", &caps[1]);
+                if !names.contains(&name){
+                    names.push(name);
+                }
+            }
+        }
+    }
+    let subprograms: HashMap<String, String> = define_regex.captures_iter(file_as_string)
+        .map(|caps| (format!("@{}", &caps[1]), caps[2].to_string()))
+        .collect();
+    names.into_iter().map(|name| DifferentiatedFunction{
+        subprogram: subprograms.get(&name).cloned(),
+        name
+    }).collect()
+}
+
+/// Extracts `!1485, !1489` into a `vec!["!1485", "!1489"]`
+fn extract_llvm_multiple_tags(tags: &str, regex: &Regex) -> Vec<String>{
+    let mut tags_vec = vec![];
+    for caps in regex.captures_iter(tags) {
+        assert_eq!(caps.len(), 2);
+        tags_vec.push(caps[1].to_string());
+    }
+    tags_vec
+}
+
+/// Turns strings such as `tag: DW_TAG_pointer_type, name: "&mut alloc::vec::Vec<f64>", baseType: !6`
+/// into a `tag` => `DW_TAG_pointer_type`, `name` => `&mut alloc::vec::Vec<f64>` etc HashMap.
+/// `null` fields are left out.
+fn get_all_params(input: &str) -> Result<HashMap<String, String>, String>{
+    Ok(parse_fields(input)?
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.as_text()?)))
+        .collect())
 }
 
 
 pub fn extract_llvm_multiple_tags_tag(file_as_string: &str) -> HashMap<String, Vec<String>>{
     // works on lines like: @llvm.dbg.value(metadata %"std::fmt::Formatter"* %f, metadata !214, metadata !DIExpression()), !dbg !217
-    let line_number_and_tags = r#"(!\d+) = !\{(!.+)?\}"#;
+    let line_number_and_tags = r#"(!\d+) = (?:distinct )?!\{(!.+)?\}"#;
     let regex = Regex::new(line_number_and_tags).unwrap();
+    let tag_regex = Regex::new(r#"(!\d+)"#).unwrap();
     let mut multiple_tags_tag_vec = HashMap::new();
-    for caps in regex.captures_iter(&file_as_string){
+    for caps in regex.captures_iter(file_as_string){
         assert_eq!(caps.len(), 3); // don't trust this, capture group could have no group
         let location_tag = &caps[1];
         let tags = if let Some(tags) = &caps.get(2){
-            extract_llvm_multiple_tags(tags.as_str())
+            extract_llvm_multiple_tags(tags.as_str(), &tag_regex)
         }else{
             vec![]
         };
@@ -40,35 +113,95 @@ pub fn extract_llvm_multiple_tags_tag(file_as_string: &str) -> HashMap<String, V
 }
 
 /// Parses lines like: @llvm.dbg.value(metadata %"std::fmt::Formatter"* %f, metadata !214, metadata !DIExpression()), !dbg !217
-pub fn extract_llvm_to_rust_metadata(file_as_string: &str) -> Vec<LLVMLocalTypeVariableDebugInfo>{
-    let debug_value_regex = r#"@llvm\.dbg\.value\(metadata (\S+) (\S+), metadata ([^,]+), metadata !DIExpression\(\)\)"#;
+/// as well as `llvm.dbg.declare`, `llvm.dbg.addr` and the `#dbg_value(ptr %f, !214, !DIExpression(), !217)`
+/// and `#dbg_declare` debug records of newer LLVMs
+pub fn extract_llvm_to_rust_metadata(file_as_string: &str) -> Result<Vec<LLVMLocalTypeVariableDebugInfo>, String>{
+    let debug_value_regex = r#"(?:@llvm\.dbg\.(value|declare|addr)\(metadata (.+?) (\S+), metadata (!\d+), metadata (!DIExpression\([^)]*\))\))|(?:#dbg_(value|declare)\((.+?) (\S+), (!\d+), (!DIExpression\([^)]*\)),)"#;
     let regex = Regex::new(debug_value_regex).unwrap();
     let mut debug_metadatas = vec![];
-    for caps in regex.captures_iter(&file_as_string){
-        assert_eq!(caps.len(), 4);
+    for caps in regex.captures_iter(file_as_string){
+        assert_eq!(caps.len(), 11);
+        // the first capture contains the whole regex match, then come the intrinsic call groups
+        // or the debug record ones
+        let group = |index: usize| caps.get(index).or_else(|| caps.get(index + 5)).unwrap().as_str();
+        let expression = group(5);
         debug_metadatas.push(LLVMLocalTypeVariableDebugInfo {
-            // the first capture contains the whole regex match
-            local_var_type: caps[1].to_string(),
-            local_var_name: caps[2].to_string(),
-            location_tag: caps[3].to_string()
+            local_var_type: group(2).to_string(),
+            local_var_name: group(3).to_string(),
+            location_tag: group(4).to_string(),
+            intrinsic: DebugIntrinsic::try_from(group(1)).unwrap(),
+            expression: DIExpression::try_from(expression).map_err(|e| format!("debug info of {}: {}", group(3), e))?
         });
     };
-    debug_metadatas
+    Ok(debug_metadatas)
 }
 
-pub fn extract_rust_metadata(file_as_string: &str) -> Vec<LLVMDebugTypeInformation>{
-    let debug_value_description = r#"(!\d+) = (!\S+)\((.+)\)"#;
-    let re = Regex::new(debug_value_description).unwrap();
+/// Parses lines like `!1298 = !DIDerivedType(tag: DW_TAG_pointer_type, ...)`, `distinct` ones included.
+/// Goes line by line instead of using a regex, which is much slower on large modules.
+/// Nodes whose fields can't be read are left out with a warning, they rarely describe a type.
+pub fn extract_rust_metadata(file_as_string: &str) -> (Vec<LLVMDebugTypeInformation>, Vec<String>){
     let mut debug_metadatas_explanation = vec![];
-    for caps in re.captures_iter(&file_as_string){
-        assert_eq!(caps.len(), 4);
+    let mut warnings = vec![];
+    for line in file_as_string.lines(){
+        let (location_tag, node) = match line.split_once(" = "){
+            Some((location_tag, node)) if location_tag.len() > 1 && location_tag.starts_with('!') && location_tag[1..].bytes().all(|b| b.is_ascii_digit()) => (location_tag, node),
+            _ => continue,
+        };
+        let (distinct, node) = match node.strip_prefix("distinct "){
+            Some(node) => (true, node),
+            None => (false, node),
+        };
+        let (variant, parameters) = match (node.find('('), node.rfind(')')){
+            (Some(open), Some(close)) if node.starts_with('!') && open < close && node[1..open].bytes().all(|b| b.is_ascii_alphanumeric()) => {
+                (&node[..open], &node[open + 1..close])
+            }
+            _ => continue,
+        };
+        let parameters = match get_all_params(parameters){
+            Ok(parameters) => parameters,
+            Err(e) => {
+                warnings.push(format!("metadata {} left out: {}", location_tag, e));
+                continue;
+            }
+        };
         debug_metadatas_explanation.push(LLVMDebugTypeInformation {
-            location_tag: caps[1].to_string(),
-            variant: caps[2].to_string(),
-            parameters: get_all_params(caps[3].to_string())
+            location_tag: location_tag.to_string(),
+            distinct,
+            variant: variant.to_string(),
+            parameters
         });
     }
-    debug_metadatas_explanation
+    (debug_metadatas_explanation, warnings)
 }
 
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn tuples(){
+        let ir = "\
+!4 = !{!5, !6}
+!7 = distinct !{!7, !8, !9}
+!10 = !{}
+";
+        let tuples = extract_llvm_multiple_tags_tag(ir);
+        assert_eq!(tuples.len(), 3);
+        assert_eq!(tuples["!4"], vec!["!5", "!6"]);
+        assert_eq!(tuples["!7"], vec!["!7", "!8", "!9"]);
+        assert!(tuples["!10"].is_empty());
+    }
 
+    #[test]
+    fn unreadable_nodes_are_left_out(){
+        let ir = "\
+!5 = !DITemplateValueParameter(name: \"F\", type: !6, value: ptr @x)
+!6 = !DIBasicType(name: \"f64\", size: 64, encoding: DW_ATE_float)
+";
+        let (nodes, warnings) = extract_rust_metadata(ir);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].location_tag, "!6");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("metadata !5 left out: "), "{}", warnings[0]);
+    }
+}
@@ -0,0 +1,399 @@
+//! Functions Enzyme should not differentiate, such as the formatting behind `println!` or the panics
+//! of bounds checks, found by the path of their Rust symbol and given the `"enzyme_inactive"` attribute
+use crate::ir_syntax::split_arguments;
+
+/// Std machinery that never carries derivatives. A path ending with `::*` matches everything under it.
+pub const DEFAULT_INACTIVE: [&str; 4] = [
+    "core::fmt::*",
+    "std::io::*",
+    "core::panicking::*",
+    "alloc::alloc::handle_alloc_error",
+];
+
+const ENZYME_INACTIVE: &str = "\"enzyme_inactive\"";
+
+/// Reads the paths of a config file listing inactive functions, one per line, `#` starts a comment:
+///
+/// ```text
+/// # logging never changes the result
+/// my_crate::log::*
+/// my_crate::report_progress
+/// ```
+pub fn parse_inactive_config(config: &str) -> Result<Vec<String>, String>{
+    let mut paths = vec![];
+    for (index, line) in config.lines().enumerate(){
+        let path = line.split('#').next().unwrap().trim();
+        if path.is_empty(){
+            continue;
+        }
+        if path.contains(char::is_whitespace) || path.starts_with("::") || path.ends_with("::"){
+            return Err(format!("line {}: `{}` is not a path such as `my_crate::log::*`", index + 1, path));
+        }
+        paths.push(path.to_string());
+    }
+    Ok(paths)
+}
+
+/// Adds `"enzyme_inactive"` to the `declare` and `define` of every function whose symbol demangles to
+/// one of `paths`. Returns the IR and the paths of the functions marked.
+pub fn mark_inactive_functions(ir: &str, paths: &[String]) -> (String, Vec<SymbolPath>){
+    let mut marked = vec![];
+    let mut rewritten = String::with_capacity(ir.len());
+    for line in ir.split_inclusive('\n'){
+        let found = if line.starts_with("declare ") || line.starts_with("define "){
+            symbol_attributes_position(line).and_then(|(symbol, position)| {
+                let path = demangle(symbol)?;
+                if paths.iter().any(|pattern| path.matches(pattern)) && !line.contains(ENZYME_INACTIVE){
+                    Some((path, position))
+                }else{
+                    None
+                }
+            })
+        }else{
+            None
+        };
+        match found{
+            Some((path, position)) => {
+                rewritten.push_str(&line[..position]);
+                rewritten.push(' ');
+                rewritten.push_str(ENZYME_INACTIVE);
+                rewritten.push_str(&line[position..]);
+                marked.push(path);
+            }
+            None => rewritten.push_str(line),
+        }
+    }
+    (rewritten, marked)
+}
+
+/// The symbol of the function of a `declare` or `define` line, and where its function attributes go:
+/// after the parameters and the `unnamed_addr` or `addrspace` that may follow them
+fn symbol_attributes_position(line: &str) -> Option<(&str, usize)>{
+    let at = line.find('@')?;
+    let symbol_end = if line[at + 1..].starts_with('"'){
+        at + 2 + line[at + 2..].find('"')? + 1
+    }else{
+        at + 1 + line[at + 1..].find('(')?
+    };
+    let symbol = line[at + 1..symbol_end].trim_matches('"');
+    if !line[symbol_end..].starts_with('('){
+        return None;
+    }
+    let (_, args_end) = split_arguments(&line[symbol_end + 1..]).ok()?;
+    let mut position = symbol_end + 1 + args_end + 1;
+    loop{
+        let rest = &line[position..];
+        let word = rest.trim_start().split(char::is_whitespace).next().unwrap_or("");
+        if word == "unnamed_addr" || word == "local_unnamed_addr" || word.starts_with("addrspace("){
+            position += rest.len() - rest.trim_start().len() + word.len();
+        }else{
+            return Some((symbol, position));
+        }
+    }
+}
+
+/// The Rust path of a symbol, such as `core::panicking::panic_bounds_check`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolPath{
+    pub path: String,
+    /// Whether `path` goes down to the function. Only the module of the `impl` a method is in is
+    /// known for v0 symbols: `<f64 as Display>::fmt` is `core::fmt::float`.
+    pub complete: bool,
+}
+
+impl SymbolPath{
+    /// Whether the function is `pattern`, or under it for a pattern such as `core::fmt::*`
+    pub fn matches(&self, pattern: &str) -> bool{
+        // the trait of `<&T as core::fmt::Display>::fmt` tells what the function is about too
+        let trait_path = self.path.strip_prefix('<')
+            .and_then(|path| path.split_once(" as "))
+            .map(|(_, path)| path.replacen('>', "", 1));
+        std::iter::once(self.path.as_str()).chain(trait_path.as_deref()).any(|path| match pattern.strip_suffix("::*"){
+            Some(prefix) => path == prefix || path.starts_with(&format!("{}::", prefix)),
+            None => self.complete && path == pattern,
+        })
+    }
+}
+
+/// Demangles legacy `_ZN...E` and v0 `_R...` Rust symbols, `None` for any other symbol
+pub fn demangle(symbol: &str) -> Option<SymbolPath>{
+    if let Some(mangled) = symbol.strip_prefix("_ZN"){
+        return demangle_legacy(mangled).map(|path| SymbolPath{ path, complete: true });
+    }
+    let mangled = symbol.strip_prefix("_R")?;
+    let mut parser = V0Parser{
+        mangled: mangled.as_bytes(),
+        position: 0,
+        depth: 0,
+    };
+    // the optional encoding version
+    while parser.peek()?.is_ascii_digit(){
+        parser.position += 1;
+    }
+    let (path, complete) = parser.path()?;
+    Some(SymbolPath{
+        path: path.join("::"),
+        complete
+    })
+}
+
+/// `3std2io5stdio6_print17h0123456789abcdefE` into `std::io::stdio::_print`
+fn demangle_legacy(mangled: &str) -> Option<String>{
+    let mut items = vec![];
+    let mut rest = mangled;
+    while !rest.starts_with('E'){
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let length: usize = rest[..digits].parse().ok()?;
+        let item = rest.get(digits..digits + length)?;
+        rest = &rest[digits + length..];
+        items.push(item);
+    }
+    let is_hash = |item: &str| item.len() == 17 && item.starts_with('h') && item[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if items.last().is_some_and(|item| is_hash(item)){
+        items.pop();
+    }
+    let items: Vec<String> = items.into_iter().map(|item| decode_legacy_item(item.strip_prefix('_').filter(|item| item.starts_with('$')).unwrap_or(item))).collect();
+    Some(items.join("::"))
+}
+
+/// Decodes the escapes of a legacy item, such as `$LT$$RF$T$u20$as$u20$core..fmt..Display$GT$`
+fn decode_legacy_item(item: &str) -> String{
+    let mut decoded = String::new();
+    let mut rest = item;
+    while !rest.is_empty(){
+        if let Some(escape) = rest.strip_prefix('$').and_then(|escape| escape.split_once('$')){
+            let (code, after) = escape;
+            let replacement = match code{
+                "SP" => Some('@'),
+                "BP" => Some('*'),
+                "RF" => Some('&'),
+                "LT" => Some('<'),
+                "GT" => Some('>'),
+                "LP" => Some('('),
+                "RP" => Some(')'),
+                "C" => Some(','),
+                _ => code.strip_prefix('u').and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(char::from_u32),
+            };
+            if let Some(replacement) = replacement{
+                decoded.push(replacement);
+                rest = after;
+                continue;
+            }
+        }
+        if let Some(after) = rest.strip_prefix(".."){
+            decoded.push_str("::");
+            rest = after;
+            continue;
+        }
+        let c = rest.chars().next().unwrap();
+        decoded.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    decoded
+}
+
+/// Reads the paths of v0 symbols, see the v0 symbol format of the rustc book. Types are not decoded,
+/// so the path stops at the first `impl` or generic arguments.
+struct V0Parser<'a>{
+    mangled: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl V0Parser<'_>{
+    fn peek(&self) -> Option<u8>{
+        self.mangled.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<u8>{
+        let next = self.peek()?;
+        self.position += 1;
+        Some(next)
+    }
+
+    /// `_` is 0, otherwise base 62 digits ended by `_` give their value plus one
+    fn base62(&mut self) -> Option<usize>{
+        let mut value = 0usize;
+        let mut digits = 0;
+        loop{
+            let digit = match self.next()?{
+                b'_' => return Some(if digits == 0{ 0 }else{ value.checked_add(1)? }),
+                c @ b'0'..=b'9' => c - b'0',
+                c @ b'a'..=b'z' => c - b'a' + 10,
+                c @ b'A'..=b'Z' => c - b'A' + 36,
+                _ => return None,
+            };
+            value = value.checked_mul(62)?.checked_add(digit as usize)?;
+            digits += 1;
+        }
+    }
+
+    fn disambiguator(&mut self) -> Option<()>{
+        if self.peek() == Some(b's'){
+            self.position += 1;
+            self.base62()?;
+        }
+        Some(())
+    }
+
+    /// A length prefixed identifier such as `9panicking`, or `6__print` for `_print`
+    fn identifier(&mut self) -> Option<String>{
+        self.disambiguator()?;
+        if self.peek() == Some(b'u'){
+            // punycode, kept encoded
+            self.position += 1;
+        }
+        let start = self.position;
+        while self.peek()?.is_ascii_digit(){
+            self.position += 1;
+        }
+        let length: usize = std::str::from_utf8(&self.mangled[start..self.position]).ok()?.parse().ok()?;
+        if self.peek() == Some(b'_'){
+            self.position += 1;
+        }
+        let identifier = self.mangled.get(self.position..self.position + length)?;
+        self.position += length;
+        String::from_utf8(identifier.to_vec()).ok()
+    }
+
+    /// The identifiers of a path, and whether they go down to its end
+    fn path(&mut self) -> Option<(Vec<String>, bool)>{
+        self.depth += 1;
+        if self.depth > 64{
+            return None;
+        }
+        let path = match self.next()?{
+            b'C' => Some((vec![self.identifier()?], true)),
+            b'N' => {
+                let namespace = self.next()?;
+                let (mut path, complete) = self.path()?;
+                if complete{
+                    let identifier = self.identifier()?;
+                    // closures and shims are in upper case namespaces
+                    path.push(match (namespace.is_ascii_uppercase(), identifier.is_empty()){
+                        (true, true) => format!("{{{}}}", namespace as char),
+                        _ => identifier,
+                    });
+                }
+                Some((path, complete))
+            }
+            b'M' | b'X' => {
+                self.disambiguator()?;
+                let (path, _) = self.path()?;
+                Some((path, false))
+            }
+            b'I' => {
+                let (path, _) = self.path()?;
+                Some((path, false))
+            }
+            b'B' => {
+                let target = self.base62()?;
+                // back references only point backwards, which also keeps this from looping
+                if target >= self.position{
+                    return None;
+                }
+                let resume = self.position;
+                self.position = target;
+                let path = self.path();
+                self.position = resume;
+                path
+            }
+            _ => None,
+        };
+        self.depth -= 1;
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn path(symbol: &str) -> String{
+        demangle(symbol).unwrap().path
+    }
+
+    #[test]
+    fn v0_symbols(){
+        assert_eq!(path("_RNvNtCsgEmfK2I1SDS_4core9panicking18panic_bounds_check"), "core::panicking::panic_bounds_check");
+        assert_eq!(path("_RNvNtNtCsjrHSEGnQ3l9_3std2io5stdio6__print"), "std::io::stdio::_print");
+        assert_eq!(path("_RNvCsfLfy6EI15iL_7___rustc12___rust_alloc"), "__rustc::__rust_alloc");
+        // `<f64 as core::fmt::Display>::fmt`, implemented in `core::fmt::float`
+        let display = demangle("_RNvXs7_NtNtCsgEmfK2I1SDS_4core3fmt5floatdNtB7_7Display3fmt").unwrap();
+        assert_eq!(display, SymbolPath{ path: "core::fmt::float".to_string(), complete: false });
+        assert!(display.matches("core::fmt::*"));
+        assert!(!display.matches("core::fmt::float"));
+    }
+
+    #[test]
+    fn legacy_symbols(){
+        assert_eq!(path("_ZN4core9panicking9panic_fmt17h0123456789abcdefE"), "core::panicking::panic_fmt");
+        let display = demangle("_ZN44_$LT$$RF$T$u20$as$u20$core..fmt..Display$GT$3fmt17h4f3ab1d128dd6c5fE").unwrap();
+        assert_eq!(display.path, "<&T as core::fmt::Display>::fmt");
+        assert!(display.matches("core::fmt::*"));
+        assert_eq!(demangle("_ZN4core3fmt"), None);
+        assert_eq!(demangle("malloc"), None);
+    }
+
+    #[test]
+    fn matching(){
+        let handle_alloc_error = demangle("_ZN5alloc5alloc18handle_alloc_error17h0123456789abcdefE").unwrap();
+        assert!(handle_alloc_error.matches("alloc::alloc::handle_alloc_error"));
+        assert!(handle_alloc_error.matches("alloc::*"));
+        assert!(!handle_alloc_error.matches("alloc::alloc::handle"));
+        assert!(!handle_alloc_error.matches("alloc::all::*"));
+    }
+
+    #[test]
+    fn inactive_functions_get_the_attribute(){
+        let ir = "\
+define double @_ZN4main4loss17h0123456789abcdefE(ptr %x) unnamed_addr #0 {
+  ret double 0.0
+}
+declare void @_RNvNtCsgEmfK2I1SDS_4core9panicking18panic_bounds_check(i64, i64, ptr) unnamed_addr #1
+declare void @\"_ZN3std2io5stdio6_print17h0123456789abcdefE\"(ptr noalias nocapture noundef dereferenceable(48))
+declare void @_ZN4main3log17h0123456789abcdefE(ptr)
+";
+        let mut paths: Vec<String> = DEFAULT_INACTIVE.iter().map(|path| path.to_string()).collect();
+        paths.extend(parse_inactive_config("# mine\nmain::log  # too\n").unwrap());
+        let (marked_ir, marked) = mark_inactive_functions(ir, &paths);
+        assert_eq!(marked_ir, "\
+define double @_ZN4main4loss17h0123456789abcdefE(ptr %x) unnamed_addr #0 {
+  ret double 0.0
+}
+declare void @_RNvNtCsgEmfK2I1SDS_4core9panicking18panic_bounds_check(i64, i64, ptr) unnamed_addr \"enzyme_inactive\" #1
+declare void @\"_ZN3std2io5stdio6_print17h0123456789abcdefE\"(ptr noalias nocapture noundef dereferenceable(48)) \"enzyme_inactive\"
+declare void @_ZN4main3log17h0123456789abcdefE(ptr) \"enzyme_inactive\"
+");
+        let marked: Vec<&str> = marked.iter().map(|path| path.path.as_str()).collect();
+        assert_eq!(marked, vec!["core::panicking::panic_bounds_check", "std::io::stdio::_print", "main::log"]);
+        assert_eq!(mark_inactive_functions(&marked_ir, &paths).0, marked_ir);
+    }
+
+    #[test]
+    fn attributes_go_after_the_parameters(){
+        let line = "define internal fastcc void @_ZN4main3log17h0123456789abcdefE(ptr align 8 %0, i64 %1) unnamed_addr #3 {";
+        let (symbol, position) = symbol_attributes_position(line).unwrap();
+        assert_eq!(symbol, "_ZN4main3log17h0123456789abcdefE");
+        assert_eq!(&line[position..], " #3 {");
+        let quoted = "declare void @\"_ZN3std2io5stdio6_print17h0123456789abcdefE\"(ptr, { i64, ptr }) local_unnamed_addr addrspace(0)";
+        let (symbol, position) = symbol_attributes_position(quoted).unwrap();
+        assert_eq!(symbol, "_ZN3std2io5stdio6_print17h0123456789abcdefE");
+        assert_eq!(&quoted[position..], "");
+        assert_eq!(symbol_attributes_position("declare void @\"unterminated(ptr)"), None);
+    }
+
+    #[test]
+    fn already_inactive_functions_are_left_alone(){
+        let ir = "define internal fastcc void @_ZN4main3log17h0123456789abcdefE(ptr %0) unnamed_addr \"enzyme_inactive\" #3 {\n";
+        let (marked_ir, marked) = mark_inactive_functions(ir, &["main::log".to_string()]);
+        assert_eq!(marked_ir, ir);
+        assert!(marked.is_empty());
+    }
+
+    #[test]
+    fn invalid_configs(){
+        assert!(parse_inactive_config("my crate::log").is_err());
+        assert!(parse_inactive_config("ok::path\n::log").is_err());
+    }
+}
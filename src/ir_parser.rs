@@ -1,90 +1,259 @@
-//! aw
-//!
-use regex::Regex;
-use std::collections::HashMap;
+//! `ir-parser`: recovers the Rust types of the local variables of a LLVM IR (.ll) file from its
+//! debug information
+use std::collections::{BTreeMap, HashMap};
 use crate::structs::*;
+use crate::cli::{parse_args, read_input, write_output};
+use crate::file_parser::LLVMIRMetadata;
+use crate::type_layout::{type_layout, Layout};
+use crate::type_tree::TypeTree;
+use crate::type_injection::{inject_type_trees, type_copies};
 
+mod cli;
 mod structs;
 mod file_parser;
+mod metadata_fields;
+mod metadata_store;
 mod ir_transverser;
+mod type_layout;
+mod type_tree;
+mod type_injection;
+#[cfg(test)]
+mod bench;
 
-pub struct LLVMIRMetadata{
-    pub multiple_tags_tag: HashMap<String, Vec<String>>,
-    pub llvm_local_type_variable_debug_info: Vec<LLVMLocalTypeVariableDebugInfo>,
-    pub llvm_debug_type_information: Vec<LLVMDebugTypeInformation>
-}
+const USAGE: &str = "\
+Recovers the Rust types of the local variables of a LLVM IR (.ll) file from its debug information
+
+Usage: ir-parser [INPUT] [-o OUTPUT] [--type-trees | --inject-types]
+
+    INPUT            IR to analyse, `-` or nothing reads stdin
+    -o, --output     Where to write the recovered types, `-` or nothing writes stdout
+    --type-trees     Write the Enzyme TypeTree of each argument of the differentiated functions instead
+    --inject-types   Write the IR with these TypeTrees as `enzyme_type` attributes of the parameters, and the
+                     memcpy/memmove the differentiated functions reach preceded by markers of what they copy, instead
+";
 
-/// Extracts `!1485, !1489` into a `vec!["!1485", "!1489"]`
-fn extract_llvm_multiple_tags(tags: &str) -> Vec<String>{
-    let tag_regex = r#"(!\d+)"#;
-    let regex = Regex::new(tag_regex).unwrap();
-    let mut tags_vec = vec![];
-    for caps in regex.captures_iter(tags) {
-        assert_eq!(caps.len(), 2);
-        tags_vec.push(caps[1].to_string());
+pub fn main(){
+    if let Err(e) = run(){
+        eprintln!("error: {}", e);
+        eprintln!("Run `ir-parser --help` for usage");
+        std::process::exit(2);
     }
-    tags_vec
 }
 
-/// Turns strings such as `tag: DwTagPointerType, name: "&mut alloc::vec::Vec<f64>", baseType: !6`
-/// into a `tag` => `DwTagPointerType`, `name` => `&mut alloc::vec::Vec<f64>` etc HashMap
-fn get_all_params(input: String) -> HashMap<String, String>{
-    let debug_value_description = r#"([^:\s]+):\s([^,]+)[,\s]?"#;
-    let re = Regex::new(debug_value_description).unwrap();
-    let mut debug_metadatas: HashMap<String, String> = HashMap::new();
-    for caps in re.captures_iter(&input){
-        debug_metadatas.insert(caps[1].to_string(), caps[2].to_string());
+fn run() -> Result<(), String>{
+    let args = parse_args(std::env::args(), &["--type-trees", "--inject-types"], &[])?;
+    if args.help{
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    let input = read_input(&args.input)?;
+    let ir = LLVMIRMetadata::new(&input)?;
+    for warning in &ir.warnings{
+        eprintln!("warning: {}", warning);
+    }
+    if args.flag("--inject-types"){
+        let parameters = inject_type_trees(&input, &ir)?;
+        let copies = type_copies(&parameters.ir, &ir)?;
+        for warning in parameters.warnings.iter().chain(&copies.warnings){
+            eprintln!("warning: {}", warning);
+        }
+        return write_output(&args.output, &copies.ir);
+    }
+    if args.flag("--type-trees"){
+        let mut output = String::new();
+        for (function, arguments) in argument_type_trees(&ir){
+            output.push_str(&format!("{}\n", function.name));
+            for argument in arguments{
+                output.push_str(&format!("    {} {}: {}\n", argument.arg, argument.name.as_deref().unwrap_or("_"), argument.type_tree));
+            }
+        }
+        return write_output(&args.output, &output);
     }
-    debug_metadatas
+    let variables = recover_variables(&ir);
+    write_output(&args.output, &format!("{:#?}\n", variables))
 }
 
-pub fn get_rust_debug_metadata(tag: &str, metadata_vec: &[LLVMDebugTypeInformation]) -> Option<LLVMDebugTypeInformation>{
-    metadata_vec
-        .iter()
-        .find(|a| a.location_tag == tag).cloned()
+/// An argument of a differentiated function, as numbered by its `DILocalVariable`
+#[derive(Clone, Debug)]
+pub struct ArgumentTypeTree{
+    pub arg: u32,
+    pub name: Option<String>,
+    /// Id of the `DILocalVariable`
+    pub location_tag: String,
+    pub type_tree: TypeTree,
 }
 
+/// The TypeTree of every argument of each function Enzyme differentiates in `ir`, from the
+/// `DILocalVariable`s of the function. Functions without debug info have no arguments.
+pub fn argument_type_trees(ir: &LLVMIRMetadata) -> Vec<(DifferentiatedFunction, Vec<ArgumentTypeTree>)>{
+    let mut ast = Ast{
+        inner: HashMap::new()
+    };
+    ir.differentiated_functions.iter().map(|function| {
+        let mut arguments: Vec<(u32, &str)> = ir.metadata.nodes()
+            .filter(|node| matches!(node.get_variant(), Some(Variant::LocalVariable)))
+            .filter(|node| function.subprogram.is_some() && node.parameters.get("scope") == function.subprogram.as_ref())
+            .filter_map(|node| Some((node.parameters.get("arg")?.parse().ok()?, node.location_tag.as_str())))
+            .collect();
+        arguments.sort();
+        let arguments = arguments.into_iter().map(|(arg, location_tag)| {
+            match ast.get_or_parse(location_tag, &ir.metadata){
+                Some(TypeAST::DILocalVariable(local)) => ArgumentTypeTree{
+                    arg,
+                    name: local.name.clone(),
+                    location_tag: location_tag.to_string(),
+                    type_tree: TypeTree::from_layout(&type_layout(&ast, &local.r#type))
+                },
+                _ => panic!("Debug info for local var was not of type for local var!"),
+            }
+        }).collect();
+        (function.clone(), arguments)
+    }).collect()
+}
 
-
-
-pub fn main(){
-    let ir = LLVMIRMetadata::new("oxide_enzyme_replaced.ll");
+/// The type of every local variable of `ir` described by a debug intrinsic, by variable id
+pub fn recover_variables(ir: &LLVMIRMetadata) -> BTreeMap<String, RecoveredVariable>{
     let mut ast = Ast{
         inner: HashMap::new()
     };
-    for local_llvm_type in &ir.llvm_debug_type_information {
-        if matches!(local_llvm_type.get_variant().expect(&format!("No Variant for {:?}", local_llvm_type)), Variant::LocalVariable | Variant::BasicType | Variant::DerivedType | Variant::CompositeType | Variant::TemplateTypeParameter | Variant::DISubroutineType){
-            let parsed_variant = parse_llvm_debug_type_information(&local_llvm_type, &ir.llvm_debug_type_information, &ir.multiple_tags_tag);
-            ast.inner.insert(local_llvm_type.location_tag.clone(), parsed_variant);
-        }
-    }
 
-    let mut local_types_with_metadata: HashMap<LLVMLocalTypeVariableDebugInfo, DILocalVariable>  = HashMap::new();
-    let mut local_types_with_basic_types: HashMap<LLVMLocalTypeVariableDebugInfo, BasicTypeOrCompositeType>  = HashMap::new();
+    // after SROA a variable can be carried by several values, each with a `DW_OP_LLVM_fragment`
+    let mut variables: BTreeMap<String, RecoveredVariable> = BTreeMap::new();
     for val in &ir.llvm_local_type_variable_debug_info{
-        let debug_info = ast.inner.get(&val.location_tag).expect(&format!("No debug info for: {:?}", val));
-        if let TypeAST::DILocalVariable(local) = debug_info {
-            let mut result = Vec::new();
-            local_var_unroll_into_basic_types(local, &mut result);
-            assert_eq!(result.len(), 1);
-            // println!("{:?} => {:#?}", local.name , result);
-            local_types_with_metadata.insert(val.clone(), local.clone());
-            local_types_with_basic_types.insert(val.clone(), result.pop().unwrap());
+        let debug_info = ast.get_or_parse(&val.location_tag, &ir.metadata).unwrap_or_else(|| panic!("No debug info for: {:?}", val));
+        if let TypeAST::DILocalVariable(local) = &debug_info {
+            let variable = variables.entry(val.location_tag.clone()).or_insert_with(|| {
+                let mut result = Vec::new();
+                local_var_unroll_into_basic_types(local, &ast, &mut result);
+                assert_eq!(result.len(), 1);
+                RecoveredVariable{
+                    name: local.name.clone(),
+                    location_tag: val.location_tag.clone(),
+                    r#type: result.pop().unwrap(),
+                    layout: type_layout(&ast, &local.r#type),
+                    values: vec![]
+                }
+            });
+            if !variable.values.iter().any(|known| &known.value == val){
+                variable.values.push(VariableValue{
+                    value: val.clone(),
+                    field: field_path(&ast, &local.r#type, val.expression.fragment())
+                });
+            }
         }else{
             panic!("Debug info for local var was not of type for local var!");
         }
     }
+    variables
+}
+
+/// A `DILocalVariable` with its type and every LLVM value describing it
+#[derive(Clone, Debug)]
+pub struct RecoveredVariable{
+    pub name: Option<String>,
+    pub location_tag: String,
+    pub r#type: BasicTypeOrCompositeType,
+    pub layout: Layout,
+    pub values: Vec<VariableValue>,
+}
 
-    println!("{:#?}", local_types_with_basic_types);
+#[derive(Clone, Debug)]
+pub struct VariableValue{
+    pub value: LLVMLocalTypeVariableDebugInfo,
+    /// The part of the variable this value carries, such as `Tensor.data.buf.ptr`, or just
+    /// `Tensor` for the whole variable
+    pub field: String,
+}
 
+fn parse_bits(bits: &Option<String>) -> Option<u64>{
+    bits.as_ref()?.parse().ok()
+}
 
+fn type_name(ast: &Ast, id: &str) -> Option<String>{
+    match ast.get(id)?{
+        TypeAST::DerivedType(derived) => derived.name.clone(),
+        TypeAST::DIBasicType(basic) => Some(basic.name.clone()),
+        TypeAST::DICompositeType(composite) => composite.name.clone(),
+        _ => None,
+    }
+}
+
+/// Names the field of a variable of type `var_type` held by the `(offset, size)` bits of `fragment`,
+/// such as `Tensor.data.buf.ptr`. Bits that are not exactly a field are given as `[from..to]`.
+pub fn field_path(ast: &Ast, var_type: &str, fragment: Option<(u64, u64)>) -> String{
+    let mut path = vec![type_name(ast, var_type).unwrap_or_else(|| "?".to_string())];
+    let left = match (fragment, ast.get(var_type)){
+        (Some((offset, size)), Some(TypeAST::DICompositeType(composite))) => member_path(ast, composite, offset, size, &mut path),
+        (fragment, _) => fragment,
+    };
+    match left{
+        Some((offset, size)) => format!("{}[{}..{}]", path.join("."), offset, offset + size),
+        None => path.join("."),
+    }
+}
+
+/// Pushes the names of the nested members of `composite` holding the bits `offset..offset + size`.
+/// Returns `None` if the last one holds exactly those bits, otherwise the bits left relative to it.
+fn member_path(ast: &Ast, composite: &DICompositeType, offset: u64, size: u64, path: &mut Vec<String>) -> Option<(u64, u64)>{
+    for element in &composite.elements{
+        let member = match ast.get(element){
+            Some(TypeAST::DerivedType(member)) if member.tag == "DW_TAG_member" => member,
+            _ => continue,
+        };
+        let (member_offset, member_size) = match (parse_bits(&member.offset).unwrap_or(0), parse_bits(&member.size)){
+            (member_offset, Some(member_size)) => (member_offset, member_size),
+            _ => continue,
+        };
+        if offset < member_offset || offset + size > member_offset + member_size{
+            continue;
+        }
+        path.push(member.name.clone().unwrap_or_else(|| "?".to_string()));
+        if offset == member_offset && size == member_size{
+            return None;
+        }
+        // members are strictly smaller than their parent, so this ends even for recursive types
+        return match ast.get(&member.base_type){
+            Some(TypeAST::DICompositeType(inner)) => member_path(ast, inner, offset - member_offset, size, path),
+            _ => Some((offset - member_offset, size)),
+        };
+    }
+    Some((offset, size))
 }
 
 #[derive(Clone, Debug)]
 pub enum BasicTypeOrCompositeType {
     BasicType(BasicType),
     BasicPointerType(BasicPointerType),
-    Composite(String, Vec<BasicTypeOrCompositeType>)
+    Composite(String, Vec<BasicTypeOrCompositeType>),
+    /// A composite type met again while unrolling itself, such as the `Node` behind the
+    /// `Option<Box<Node>>` of a linked list, by name or id
+    Recursive(String),
+    Enum(EnumType),
+    /// A fixed-size array: its length, the stride in bits of its elements and their type
+    Array(u64, u64, Box<BasicTypeOrCompositeType>),
+    /// An array whose length is held by the variable `!N`, or unknown, and the type of its elements
+    UnknownLengthArray(Option<String>, Box<BasicTypeOrCompositeType>),
+    /// A function, such as behind a `fn(f64) -> f64` field or the entries of a vtable
+    Function,
+}
+
+/// A Rust enum: where its discriminant is and the fields of each variant
+#[derive(Clone, Debug)]
+pub struct EnumType{
+    pub identifier: String,
+    /// Offset in bits of the discriminant in the enum and its type, `None` if there is a single variant
+    pub discriminant: Option<(u64, Box<BasicTypeOrCompositeType>)>,
+    /// The discriminant overlaps a field of the variant without discriminant value, see [`EnumInfo::niche`]
+    pub niche: bool,
+    pub variants: Vec<EnumTypeVariant>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnumTypeVariant{
+    pub name: String,
+    pub discriminant: Option<i128>,
+    /// The fields of the variant
+    pub fields: Vec<BasicTypeOrCompositeType>,
 }
 
 // #[derive(Clone, Debug)]
@@ -104,39 +273,50 @@ pub struct BasicType{
     pub original: DIBasicType,
 }
 
-pub fn local_var_unroll_into_basic_types<'a>(var: &DILocalVariable, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
-    match &var.r#type{
-        LocalVariableTypes::DIDerivedType(der_var) => {
-            match der_var.base_type.as_ref(){
-                BaseType::DIDerivedType(derived) => {
-                    derived_type_unroll_into_basic_types(derived, result)
+fn resolve<'a>(ast: &'a Ast, id: &str) -> &'a TypeAST{
+    ast.get(id).expect("Invalid tag")
+}
+
+pub fn local_var_unroll_into_basic_types<'a>(var: &DILocalVariable, ast: &Ast, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
+    // ids of the composite types being unrolled, to stop at recursive ones
+    let mut visiting = vec![];
+    match resolve(ast, &var.r#type){
+        TypeAST::DerivedType(der_var) => {
+            match resolve(ast, &der_var.base_type){
+                TypeAST::DerivedType(derived) => {
+                    derived_type_unroll_into_basic_types(derived, ast, &mut visiting, result)
                 }
-                BaseType::DIBasicType(basic) => {
+                TypeAST::DIBasicType(basic) => {
                     result.push(BasicTypeOrCompositeType::BasicType(BasicType{
                         name_if_derived: None,
                         original: basic.clone()
                     }));
                     result
                 }
-                BaseType::DICompositeType(comp) => composite_type_unroll_into_basic_types(comp, result),
-                BaseType::DISubroutineType(_) => {unimplemented!()}
+                TypeAST::DICompositeType(_) => composite_type_unroll_into_basic_types(&der_var.base_type, ast, &mut visiting, result),
+                TypeAST::DISubroutineType => {
+                    result.push(BasicTypeOrCompositeType::Function);
+                    result
+                }
+                _ => panic!("Invalid type for BaseType")
             }
         }
-        LocalVariableTypes::DIBasicType(basic) => {
+        TypeAST::DIBasicType(basic) => {
             result.push(BasicTypeOrCompositeType::BasicType(BasicType{
                 name_if_derived: None,
                 original: basic.clone()
             }));
             result
         }
-        LocalVariableTypes::DICompositeType(comp) => composite_type_unroll_into_basic_types(comp, result)
+        TypeAST::DICompositeType(_) => composite_type_unroll_into_basic_types(&var.r#type, ast, &mut visiting, result),
+        _ => panic!("Invalid type for local var type")
     }
 }
 
-pub fn derived_type_unroll_into_basic_types<'a>(var: &DIDerivedType, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
-    match var.base_type.as_ref(){
-        BaseType::DIDerivedType(derived) => derived_type_unroll_into_basic_types(derived, result),
-        BaseType::DIBasicType(basic) => {
+pub fn derived_type_unroll_into_basic_types<'a>(var: &DIDerivedType, ast: &Ast, visiting: &mut Vec<String>, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
+    match resolve(ast, &var.base_type){
+        TypeAST::DerivedType(derived) => derived_type_unroll_into_basic_types(derived, ast, visiting, result),
+        TypeAST::DIBasicType(basic) => {
             if var.tag == "DW_TAG_pointer_type"{
                 result.push(BasicTypeOrCompositeType::BasicPointerType(BasicPointerType{
                     original: basic.clone()
@@ -150,30 +330,164 @@ pub fn derived_type_unroll_into_basic_types<'a>(var: &DIDerivedType, result: &'a
 
             result
         }
-        BaseType::DICompositeType(comp) => composite_type_unroll_into_basic_types(comp, result),
-        BaseType::DISubroutineType(_) => unimplemented!()
+        TypeAST::DICompositeType(_) => composite_type_unroll_into_basic_types(&var.base_type, ast, visiting, result),
+        TypeAST::DISubroutineType => {
+            result.push(BasicTypeOrCompositeType::Function);
+            result
+        }
+        _ => panic!("Invalid type for BaseType")
     }
 }
 
-pub fn composite_type_unroll_into_basic_types<'a>(var: &DICompositeType, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
+pub fn composite_type_unroll_into_basic_types<'a>(id: &str, ast: &Ast, visiting: &mut Vec<String>, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
+    let var = match resolve(ast, id){
+        TypeAST::DICompositeType(composite) => composite,
+        _ => panic!("Invalid type for CompositeType"),
+    };
+    if visiting.iter().any(|visited| visited == id){
+        result.push(BasicTypeOrCompositeType::Recursive(var.name.clone().unwrap_or_else(|| id.to_string())));
+        return result;
+    }
+    visiting.push(id.to_string());
+    if let Some(enum_info) = &var.enum_info{
+        let unrolled = enum_type_unroll(var, enum_info, ast, visiting);
+        visiting.pop();
+        result.push(BasicTypeOrCompositeType::Enum(unrolled));
+        return result;
+    }
+    if var.tag == "DW_TAG_array_type"{
+        let unrolled = array_type_unroll(var, ast, visiting);
+        visiting.pop();
+        result.push(unrolled);
+        return result;
+    }
     let mut new_vec = vec![];
     for el in &var.elements{
-        match el{
-            CompositeTypeElements::DIDerivedType(de) => {
-                derived_type_unroll_into_basic_types(de, &mut new_vec);
+        match resolve(ast, el){
+            TypeAST::DerivedType(de) => {
+                derived_type_unroll_into_basic_types(de, ast, visiting, &mut new_vec);
             }
-            CompositeTypeElements::DICompositeType(comp) => {
-                composite_type_unroll_into_basic_types(comp, &mut new_vec);
+            TypeAST::DICompositeType(_) => {
+                composite_type_unroll_into_basic_types(el, ast, visiting, &mut new_vec);
             },
-            CompositeTypeElements::DIEnumerator(_) => {}
-            CompositeTypeElements::DISubrange(_) => {}
+            TypeAST::DIEnumerator(_) => {}
+            TypeAST::DISubrange(_) => {}
+            // such as the `DISubprogram` of a method
+            TypeAST::Unsupported(_) => {}
+            _ => panic!("Invalid type for composite element")
         }
     }
+    visiting.pop();
     let new_curr = BasicTypeOrCompositeType::Composite(var.identifier.clone().unwrap_or_default(), new_vec);
     result.push(new_curr);
     result
 }
 
+/// Size in bits of the type `id`
+fn type_size(ast: &Ast, id: &str) -> Option<u64>{
+    match ast.get(id)?{
+        TypeAST::DIBasicType(basic) => parse_bits(&basic.size),
+        TypeAST::DerivedType(derived) => parse_bits(&derived.size).or_else(|| type_size(ast, &derived.base_type)),
+        TypeAST::DICompositeType(composite) => parse_bits(&composite.size),
+        _ => None,
+    }
+}
+
+fn type_unroll_into_basic_types<'a>(id: &str, ast: &Ast, visiting: &mut Vec<String>, result: &'a mut Vec<BasicTypeOrCompositeType>) -> &'a mut Vec<BasicTypeOrCompositeType>{
+    match resolve(ast, id){
+        TypeAST::DerivedType(derived) => derived_type_unroll_into_basic_types(derived, ast, visiting, result),
+        TypeAST::DIBasicType(basic) => {
+            result.push(BasicTypeOrCompositeType::BasicType(BasicType{
+                name_if_derived: None,
+                original: basic.clone()
+            }));
+            result
+        }
+        TypeAST::DICompositeType(_) => composite_type_unroll_into_basic_types(id, ast, visiting, result),
+        TypeAST::DISubroutineType => {
+            result.push(BasicTypeOrCompositeType::Function);
+            result
+        }
+        _ => panic!("Invalid type for BaseType")
+    }
+}
+
+/// The element type of the array `var` and its length, the subranges of a multidimensional array
+/// are laid out row-major. A length that doesn't fit in a `u64` is unknown.
+fn array_type_unroll(var: &DICompositeType, ast: &Ast, visiting: &mut Vec<String>) -> BasicTypeOrCompositeType{
+    let element_type = var.base_type.as_ref().expect("No baseType in DW_TAG_array_type");
+    let mut unrolled = vec![];
+    type_unroll_into_basic_types(element_type, ast, visiting, &mut unrolled);
+    let element = unrolled.pop().expect("Array element type unrolled to nothing");
+
+    let mut length = 1u64;
+    for el in &var.elements{
+        match resolve(ast, el){
+            TypeAST::DISubrange(DISubrange{count: Some(SubrangeCount::Constant(count)), ..}) => match length.checked_mul(*count){
+                Some(product) => length = product,
+                None => return BasicTypeOrCompositeType::UnknownLengthArray(None, Box::new(element)),
+            },
+            TypeAST::DISubrange(DISubrange{count: Some(SubrangeCount::Variable(variable)), ..}) => {
+                return BasicTypeOrCompositeType::UnknownLengthArray(Some(variable.clone()), Box::new(element));
+            }
+            TypeAST::DISubrange(DISubrange{count: None, ..}) => {
+                return BasicTypeOrCompositeType::UnknownLengthArray(None, Box::new(element));
+            }
+            _ => panic!("Invalid type for array subrange")
+        }
+    }
+    let stride = type_size(ast, element_type)
+        .or_else(|| parse_bits(&var.size).filter(|_| length > 0).map(|size| size / length))
+        .expect("Unknown size of array element");
+    BasicTypeOrCompositeType::Array(length, stride, Box::new(element))
+}
+
+fn enum_type_unroll(var: &DICompositeType, enum_info: &EnumInfo, ast: &Ast, visiting: &mut Vec<String>) -> EnumType{
+    let discriminant = match (&enum_info.discriminator, &var.base_type){
+        (Some(discriminator), _) => match resolve(ast, discriminator){
+            TypeAST::DerivedType(member) => {
+                let mut unrolled = vec![];
+                derived_type_unroll_into_basic_types(member, ast, visiting, &mut unrolled);
+                unrolled.pop().map(|discriminant| (parse_bits(&member.offset).unwrap_or(0), Box::new(discriminant)))
+            }
+            _ => panic!("Invalid type for discriminator")
+        },
+        // an enumeration type is its discriminant
+        (None, Some(base_type)) => match resolve(ast, base_type){
+            TypeAST::DIBasicType(basic) => Some((0, Box::new(BasicTypeOrCompositeType::BasicType(BasicType{
+                name_if_derived: None,
+                original: basic.clone()
+            })))),
+            _ => panic!("Invalid type for enumeration base type")
+        },
+        (None, None) => None,
+    };
+    let variants = enum_info.variants.iter().map(|variant| {
+        let mut unrolled = vec![];
+        if let Some(fields) = &variant.fields{
+            composite_type_unroll_into_basic_types(fields, ast, visiting, &mut unrolled);
+        }
+        // the struct of the variant unrolls into one Composite, keep its fields
+        let fields = match unrolled.pop(){
+            Some(BasicTypeOrCompositeType::Composite(_, fields)) => fields,
+            Some(other) => vec![other],
+            None => vec![],
+        };
+        EnumTypeVariant{
+            name: variant.name.clone(),
+            discriminant: variant.discriminant,
+            fields
+        }
+    }).collect();
+    EnumType{
+        // enumeration types have a name but no identifier
+        identifier: var.identifier.clone().or_else(|| var.name.clone()).unwrap_or_default(),
+        discriminant,
+        niche: enum_info.niche(),
+        variants
+    }
+}
+
 // --- Draft for unwrapping Composite types
 /*
   For structs and Composite types in general (minus arrays) it seems that we want
@@ -464,4 +778,404 @@ Some("\"linear_weights\"") => [
         name: "\"usize\"",
     },
 ]
-*/
\ No newline at end of file
+*/
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::type_layout::{LayoutEntry, LayoutKind, Pointee};
+
+    // from `let h = Holder{ f: f64::sin, scale: 3.0 };` with `struct Holder{ f: fn(f64) -> f64, scale: f64 }`
+    const FN_POINTER_IR: &str = r#"define internal double @_ZN2fp3run17h0dee8a6692e1e277E(double %x) unnamed_addr #0 !dbg !73 {
+start:
+  %h = alloca [16 x i8], align 8
+    #dbg_declare(ptr %h, !82, !DIExpression(), !91)
+  ret double %x
+}
+
+!2 = !DIFile(filename: "fp.rs", directory: "/tmp/fp")
+!16 = !DINamespace(name: "fp", scope: null)
+!19 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!20 = !{}
+!23 = !DIFile(filename: "fp.rs", directory: "/tmp/fp", checksumkind: CSK_MD5, checksum: "8e1f9dd1a2f3b0a0c7bde5a2c1e5b1c4")
+!73 = distinct !DISubprogram(name: "run", linkageName: "_ZN2fp3run17h0dee8a6692e1e277E", scope: !16, file: !23, line: 6, type: !74, scopeLine: 6, flags: DIFlagPrototyped, spFlags: DISPFlagLocalToUnit | DISPFlagDefinition, unit: !61, templateParams: !20)
+!74 = !DISubroutineType(types: !75)
+!75 = !{!19, !19}
+!82 = !DILocalVariable(name: "h", scope: !83, file: !23, line: 9, type: !84, align: 64)
+!83 = distinct !DILexicalBlock(scope: !73, file: !23, line: 9, column: 5)
+!84 = !DICompositeType(tag: DW_TAG_structure_type, name: "Holder", scope: !16, file: !2, size: 128, align: 64, flags: DIFlagPublic, elements: !85, templateParams: !20, identifier: "c99dd3997f304c9761ea7d8012771a5a")
+!85 = !{!86, !88}
+!86 = !DIDerivedType(tag: DW_TAG_member, name: "f", scope: !84, file: !2, baseType: !87, size: 64, align: 64, flags: DIFlagPublic)
+!87 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "fn(f64) -> f64", baseType: !74, size: 64, align: 64, dwarfAddressSpace: 0)
+!88 = !DIDerivedType(tag: DW_TAG_member, name: "scale", scope: !84, file: !2, baseType: !19, size: 64, align: 64, offset: 64, flags: DIFlagPublic)
+!91 = !DILocation(line: 9, column: 9, scope: !83)
+"#;
+
+    fn variables(ir: &str) -> BTreeMap<String, RecoveredVariable>{
+        recover_variables(&LLVMIRMetadata::new(ir).unwrap())
+    }
+
+    // `struct Tensor{ data: Vec<f64>, shape: [usize; 2] }` passed to `fn sum(t: Tensor)` and split by SROA in
+    // its caller, from rustc 1.95 at `-C opt-level=1`
+    const TENSOR_IR: &str = r#"define internal fastcc noundef double @_ZN2fr3sum17h5df09b956a674f3cE(ptr dead_on_return noalias noundef nonnull align 8 captures(address) dereferenceable(40) %t) unnamed_addr #0 personality ptr @rust_eh_personality !dbg !104 {
+start:
+    #dbg_declare(ptr %t, !162, !DIExpression(), !163)
+  ret double 0.0
+}
+
+define internal fastcc noundef double @_ZN2fr4make17h591ad16bf183beadE() unnamed_addr #0 personality ptr @rust_eh_personality !dbg !522 {
+start:
+    #dbg_value(i64 %err.0.i.i.i, !527, !DIExpression(DW_OP_LLVM_fragment, 0, 64), !715)
+    #dbg_value(ptr %this.1.i.i.i, !527, !DIExpression(DW_OP_LLVM_fragment, 64, 64), !715)
+    #dbg_value(i64 3, !527, !DIExpression(DW_OP_LLVM_fragment, 128, 64), !715)
+    #dbg_value(i64 3, !527, !DIExpression(DW_OP_LLVM_fragment, 192, 64), !715)
+    #dbg_value(i64 1, !527, !DIExpression(DW_OP_LLVM_fragment, 256, 64), !715)
+  ret double 0.0
+}
+
+!9 = !DIBasicType(name: "usize", size: 64, encoding: DW_ATE_unsigned)
+!23 = !{}
+!109 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!110 = !DICompositeType(tag: DW_TAG_structure_type, name: "Tensor", scope: !106, file: !2, size: 320, align: 64, flags: DIFlagPublic, elements: !111, templateParams: !23, identifier: "a2a5b46f2ee8abdd3b8bc5251b7b20f9")
+!111 = !{!112, !157}
+!112 = !DIDerivedType(tag: DW_TAG_member, name: "data", scope: !110, file: !2, baseType: !113, size: 192, align: 64, flags: DIFlagPublic)
+!113 = !DICompositeType(tag: DW_TAG_structure_type, name: "Vec<f64, alloc::alloc::Global>", scope: !114, file: !2, size: 192, align: 64, flags: DIFlagPublic, elements: !116, templateParams: !155, identifier: "719081b40ac88b8d87e285172fa12cf8")
+!116 = !{!117, !156}
+!117 = !DIDerivedType(tag: DW_TAG_member, name: "buf", scope: !113, file: !2, baseType: !118, size: 128, align: 64, flags: DIFlagPrivate)
+!118 = !DICompositeType(tag: DW_TAG_structure_type, name: "RawVec<f64, alloc::alloc::Global>", scope: !119, file: !2, size: 128, align: 64, flags: DIFlagProtected, elements: !120, templateParams: !155, identifier: "ac4d7042607b0360fbb8cdc4ffb2014e")
+!120 = !{!121, !151}
+!121 = !DIDerivedType(tag: DW_TAG_member, name: "inner", scope: !118, file: !2, baseType: !122, size: 128, align: 64, flags: DIFlagPrivate)
+!122 = !DICompositeType(tag: DW_TAG_structure_type, name: "RawVecInner<alloc::alloc::Global>", scope: !119, file: !2, size: 128, align: 64, flags: DIFlagPrivate, elements: !123, templateParams: !149, identifier: "45ea25bc7f42e606fa74c731a5e7f80c")
+!123 = !{!124, !140, !146}
+!124 = !DIDerivedType(tag: DW_TAG_member, name: "ptr", scope: !122, file: !2, baseType: !125, size: 64, align: 64, offset: 64, flags: DIFlagPrivate)
+!125 = !DICompositeType(tag: DW_TAG_structure_type, name: "Unique<u8>", scope: !126, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !127, templateParams: !135, identifier: "3c59e741c9569c78cbe381fa3cbee4e5")
+!127 = !{!128, !137}
+!128 = !DIDerivedType(tag: DW_TAG_member, name: "pointer", scope: !125, file: !2, baseType: !129, size: 64, align: 64, flags: DIFlagPrivate)
+!129 = !DICompositeType(tag: DW_TAG_structure_type, name: "NonNull<u8>", scope: !130, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !131, templateParams: !135, identifier: "ff3cb4cecc4ed13dfb833309b96ea529")
+!131 = !{!132}
+!132 = !DIDerivedType(tag: DW_TAG_member, name: "pointer", scope: !129, file: !2, baseType: !133, size: 64, align: 64, flags: DIFlagPrivate)
+!133 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*const u8", baseType: !134, size: 64, align: 64, dwarfAddressSpace: 0)
+!134 = !DIBasicType(name: "u8", size: 8, encoding: DW_ATE_unsigned)
+!135 = !{!136}
+!136 = !DITemplateTypeParameter(name: "T", type: !134)
+!137 = !DIDerivedType(tag: DW_TAG_member, name: "_marker", scope: !125, file: !2, baseType: !138, align: 8, offset: 64, flags: DIFlagPrivate)
+!138 = !DICompositeType(tag: DW_TAG_structure_type, name: "PhantomData<u8>", scope: !139, file: !2, align: 8, flags: DIFlagPublic, elements: !23, templateParams: !135, identifier: "15bae875c56c7c0f828edf19d836075e")
+!140 = !DIDerivedType(tag: DW_TAG_member, name: "cap", scope: !122, file: !2, baseType: !141, size: 64, align: 64, flags: DIFlagPrivate)
+!141 = !DICompositeType(tag: DW_TAG_structure_type, name: "UsizeNoHighBit", scope: !142, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !144, templateParams: !23, identifier: "cad7375c0c871c072d809328de25b44")
+!144 = !{!145}
+!145 = !DIDerivedType(tag: DW_TAG_member, name: "__0", scope: !141, file: !2, baseType: !9, size: 64, align: 64, flags: DIFlagPrivate)
+!146 = !DIDerivedType(tag: DW_TAG_member, name: "alloc", scope: !122, file: !2, baseType: !147, align: 8, offset: 128, flags: DIFlagPrivate)
+!147 = !DICompositeType(tag: DW_TAG_structure_type, name: "Global", scope: !148, file: !2, align: 8, flags: DIFlagPublic, elements: !23, identifier: "8471efeafbd677a88d306f08d1a0eeb")
+!149 = !{!150}
+!150 = !DITemplateTypeParameter(name: "A", type: !147)
+!151 = !DIDerivedType(tag: DW_TAG_member, name: "_marker", scope: !118, file: !2, baseType: !152, align: 8, offset: 128, flags: DIFlagPrivate)
+!152 = !DICompositeType(tag: DW_TAG_structure_type, name: "PhantomData<f64>", scope: !139, file: !2, align: 8, flags: DIFlagPublic, elements: !23, templateParams: !153, identifier: "fac14b3484ae5e1bc87b28c2b0248abe")
+!153 = !{!154}
+!154 = !DITemplateTypeParameter(name: "T", type: !109)
+!155 = !{!154, !150}
+!156 = !DIDerivedType(tag: DW_TAG_member, name: "len", scope: !113, file: !2, baseType: !9, size: 64, align: 64, offset: 128, flags: DIFlagPrivate)
+!157 = !DIDerivedType(tag: DW_TAG_member, name: "shape", scope: !110, file: !2, baseType: !158, size: 128, align: 64, offset: 192, flags: DIFlagPublic)
+!158 = !DICompositeType(tag: DW_TAG_array_type, baseType: !9, size: 128, align: 64, elements: !159)
+!159 = !{!160}
+!160 = !DISubrange(count: 2, lowerBound: 0)
+!162 = !DILocalVariable(name: "t", arg: 1, scope: !104, file: !105, line: 3, type: !110)
+!163 = !DILocation(line: 3, column: 12, scope: !104)
+!527 = !DILocalVariable(name: "t", scope: !528, file: !105, line: 6, type: !110, align: 64)
+!715 = !DILocation(line: 6, column: 9, scope: !528)
+"#;
+
+    #[test]
+    fn fragments_map_to_fields(){
+        let tensors = variables(TENSOR_IR);
+        let fields = |id: &str| -> Vec<(String, String)> {
+            tensors[id].values.iter().map(|value| (value.value.local_var_name.clone(), value.field.clone())).collect()
+        };
+        assert_eq!(fields("!162"), vec![("%t".to_string(), "Tensor".to_string())]);
+        // older LLVMs call an intrinsic instead of having a debug record
+        let intrinsic = TENSOR_IR.replace(
+            "    #dbg_declare(ptr %t, !162, !DIExpression(), !163)",
+            "  call void @llvm.dbg.declare(metadata %Tensor* %t, metadata !162, metadata !DIExpression()), !dbg !163"
+        );
+        assert_eq!(variables(&intrinsic)["!162"].values[0].field, "Tensor");
+        assert_eq!(fields("!527"), [
+            ("%err.0.i.i.i", "Tensor.data.buf.inner.cap"),
+            ("%this.1.i.i.i", "Tensor.data.buf.inner.ptr"),
+            ("3", "Tensor.data.len"),
+            // array elements are not members
+            ("3", "Tensor.shape[0..64]"),
+            ("1", "Tensor.shape[64..128]"),
+        ].iter().map(|(value, field)| (value.to_string(), field.to_string())).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fragments_across_members(){
+        let ir = LLVMIRMetadata::new(TENSOR_IR).unwrap();
+        let mut ast = Ast{ inner: HashMap::new() };
+        ast.get_or_parse("!527", &ir.metadata);
+        // the outermost member holding exactly the bits, and bits within the `usize` of `cap`
+        assert_eq!(field_path(&ast, "!110", Some((0, 128))), "Tensor.data.buf");
+        assert_eq!(field_path(&ast, "!110", Some((0, 32))), "Tensor.data.buf.inner.cap.__0[0..32]");
+        assert_eq!(field_path(&ast, "!110", Some((128, 128))), "Tensor[128..256]");
+        assert_eq!(field_path(&ast, "!110", None), "Tensor");
+    }
+
+    // `fn pick(a: Option<f64>, r: Option<&f64>, b: Option<Box<f64>>, d: Direction)` with
+    // `enum Direction{ Up, Down }`, from rustc 1.95
+    const OPTION_IR: &str = r#"define internal double @_ZN2en4pick17h955ffa748e4bc360E(i64 %a.0, double %a.1, ptr align 8 %r, ptr align 8 %0, i8 %d) unnamed_addr #1 personality ptr @rust_eh_personality !dbg !223 {
+start:
+    #dbg_declare(ptr %a.dbg.spill, !265, !DIExpression(), !268)
+    #dbg_declare(ptr %r.dbg.spill, !266, !DIExpression(), !269)
+    #dbg_declare(ptr %b, !267, !DIExpression(), !270)
+    #dbg_declare(ptr %d.dbg.spill, !271, !DIExpression(), !277)
+  ret double 0.0
+}
+
+!23 = !{}
+!37 = !DIBasicType(name: "u64", size: 64, encoding: DW_ATE_unsigned)
+!115 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "&f64", baseType: !116, size: 64, align: 64, dwarfAddressSpace: 0)
+!116 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!206 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "alloc::boxed::Box<f64, alloc::alloc::Global>", baseType: !116, size: 64, align: 64, dwarfAddressSpace: 0)
+!211 = !{!212}
+!212 = !DITemplateTypeParameter(name: "T", type: !116)
+!226 = !DICompositeType(tag: DW_TAG_structure_type, name: "Option<f64>", scope: !227, file: !2, size: 128, align: 64, flags: DIFlagPublic, elements: !228, templateParams: !23, identifier: "2dba3d5d643af83f672e49d11190e3e1")
+!228 = !{!229}
+!229 = !DICompositeType(tag: DW_TAG_variant_part, scope: !226, file: !2, size: 128, align: 64, elements: !230, templateParams: !23, identifier: "2459c3374fe6f39e654213ffe16d09ae", discriminator: !237)
+!230 = !{!231, !233}
+!231 = !DIDerivedType(tag: DW_TAG_member, name: "None", scope: !229, file: !2, baseType: !232, size: 128, align: 64, extraData: i64 0)
+!232 = !DICompositeType(tag: DW_TAG_structure_type, name: "None", scope: !226, file: !2, size: 128, align: 64, flags: DIFlagPublic, elements: !23, templateParams: !211, identifier: "66d3d0f0f14ec755afdda75addcb23df")
+!233 = !DIDerivedType(tag: DW_TAG_member, name: "Some", scope: !229, file: !2, baseType: !234, size: 128, align: 64, extraData: i64 1)
+!234 = !DICompositeType(tag: DW_TAG_structure_type, name: "Some", scope: !226, file: !2, size: 128, align: 64, flags: DIFlagPublic, elements: !235, templateParams: !211, identifier: "cffe28f075c36a0754f941d6bdb0a54f")
+!235 = !{!236}
+!236 = !DIDerivedType(tag: DW_TAG_member, name: "__0", scope: !234, file: !2, baseType: !116, size: 64, align: 64, offset: 64, flags: DIFlagPublic)
+!237 = !DIDerivedType(tag: DW_TAG_member, scope: !226, file: !2, baseType: !37, size: 64, align: 64, flags: DIFlagArtificial)
+!238 = !DICompositeType(tag: DW_TAG_structure_type, name: "Option<&f64>", scope: !227, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !239, templateParams: !23, identifier: "a974053e5c8afda57dae3dddb1f1a393")
+!239 = !{!240}
+!240 = !DICompositeType(tag: DW_TAG_variant_part, scope: !238, file: !2, size: 64, align: 64, elements: !241, templateParams: !23, identifier: "b84de29a073c61ac60c7e207ce659d19", discriminator: !250)
+!241 = !{!242, !246}
+!242 = !DIDerivedType(tag: DW_TAG_member, name: "None", scope: !240, file: !2, baseType: !243, size: 64, align: 64, extraData: i64 0)
+!243 = !DICompositeType(tag: DW_TAG_structure_type, name: "None", scope: !238, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !23, templateParams: !244, identifier: "dc07ca98e7f8a1f2e6d7df18c8d5c2b6")
+!244 = !{!245}
+!245 = !DITemplateTypeParameter(name: "T", type: !115)
+!246 = !DIDerivedType(tag: DW_TAG_member, name: "Some", scope: !240, file: !2, baseType: !247, size: 64, align: 64)
+!247 = !DICompositeType(tag: DW_TAG_structure_type, name: "Some", scope: !238, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !248, templateParams: !244, identifier: "44f75b8f57cff87ce4b5e52918f4b7a8")
+!248 = !{!249}
+!249 = !DIDerivedType(tag: DW_TAG_member, name: "__0", scope: !247, file: !2, baseType: !115, size: 64, align: 64, flags: DIFlagPublic)
+!250 = !DIDerivedType(tag: DW_TAG_member, scope: !238, file: !2, baseType: !37, size: 64, align: 64, flags: DIFlagArtificial)
+!251 = !DICompositeType(tag: DW_TAG_structure_type, name: "Option<alloc::boxed::Box<f64, alloc::alloc::Global>>", scope: !227, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !252, templateParams: !23, identifier: "b57960430a4948e74f9e4a15af84531c")
+!252 = !{!253}
+!253 = !DICompositeType(tag: DW_TAG_variant_part, scope: !251, file: !2, size: 64, align: 64, elements: !254, templateParams: !23, identifier: "8470924faa4a8dade4efbbc355ca0525", discriminator: !263)
+!254 = !{!255, !259}
+!255 = !DIDerivedType(tag: DW_TAG_member, name: "None", scope: !253, file: !2, baseType: !256, size: 64, align: 64, extraData: i64 0)
+!256 = !DICompositeType(tag: DW_TAG_structure_type, name: "None", scope: !251, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !23, templateParams: !257, identifier: "22d4efd0679a247e606ed368174f7656")
+!257 = !{!258}
+!258 = !DITemplateTypeParameter(name: "T", type: !206)
+!259 = !DIDerivedType(tag: DW_TAG_member, name: "Some", scope: !253, file: !2, baseType: !260, size: 64, align: 64)
+!260 = !DICompositeType(tag: DW_TAG_structure_type, name: "Some", scope: !251, file: !2, size: 64, align: 64, flags: DIFlagPublic, elements: !261, templateParams: !257, identifier: "3796b47b24f5c757927bed3cf541767c")
+!261 = !{!262}
+!262 = !DIDerivedType(tag: DW_TAG_member, name: "__0", scope: !260, file: !2, baseType: !206, size: 64, align: 64, flags: DIFlagPublic)
+!263 = !DIDerivedType(tag: DW_TAG_member, scope: !251, file: !2, baseType: !37, size: 64, align: 64, flags: DIFlagArtificial)
+!265 = !DILocalVariable(name: "a", arg: 1, scope: !223, file: !105, line: 2, type: !226)
+!266 = !DILocalVariable(name: "r", arg: 2, scope: !223, file: !105, line: 2, type: !238)
+!267 = !DILocalVariable(name: "b", arg: 3, scope: !223, file: !105, line: 2, type: !251)
+!268 = !DILocation(line: 2, column: 13, scope: !223)
+!269 = !DILocation(line: 2, column: 29, scope: !223)
+!270 = !DILocation(line: 2, column: 46, scope: !223)
+!271 = !DILocalVariable(name: "d", arg: 4, scope: !223, file: !105, line: 2, type: !272)
+!272 = !DICompositeType(tag: DW_TAG_enumeration_type, name: "Direction", scope: !227, file: !2, baseType: !273, size: 8, align: 8, flags: DIFlagEnumClass, elements: !274)
+!273 = !DIBasicType(name: "u8", size: 8, encoding: DW_ATE_unsigned)
+!274 = !{!275, !276}
+!275 = !DIEnumerator(name: "Up", value: 0, isUnsigned: true)
+!276 = !DIEnumerator(name: "Down", value: 1, isUnsigned: true)
+!277 = !DILocation(line: 2, column: 70, scope: !223)
+"#;
+
+    /// The variants of the enum variable `id` as `name = discriminant: fields`, `niche` for the variant
+    /// without discriminant
+    fn enum_variants(variables: &BTreeMap<String, RecoveredVariable>, id: &str) -> (Option<u64>, bool, Vec<String>){
+        let describe = |field: &BasicTypeOrCompositeType| match field{
+            BasicTypeOrCompositeType::BasicType(basic) => basic.original.name.clone(),
+            BasicTypeOrCompositeType::BasicPointerType(pointer) => format!("*{}", pointer.original.name),
+            other => format!("{:?}", other),
+        };
+        let enum_type = match &variables[id].r#type{
+            BasicTypeOrCompositeType::Composite(_, fields) => match fields.as_slice(){
+                [BasicTypeOrCompositeType::Enum(enum_type)] => enum_type,
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        };
+        let variants = enum_type.variants.iter().map(|variant| {
+            let discriminant = variant.discriminant.map_or("niche".to_string(), |discriminant| discriminant.to_string());
+            let fields: Vec<String> = variant.fields.iter().map(describe).collect();
+            format!("{} = {}: {}", variant.name, discriminant, fields.join(", "))
+        }).collect();
+        match &enum_type.discriminant{
+            Some((offset, discriminant)) => {
+                assert_eq!(describe(discriminant), "u64");
+                (Some(*offset), enum_type.niche, variants)
+            }
+            None => (None, enum_type.niche, variants),
+        }
+    }
+
+    #[test]
+    fn tagged_enums(){
+        let variables = variables(OPTION_IR);
+        assert_eq!(enum_variants(&variables, "!265"), (Some(0), false, vec!["None = 0: ".to_string(), "Some = 1: f64".to_string()]));
+        let layout = &variables["!265"].layout;
+        assert_eq!((layout.size, layout.entries.clone()), (16, vec![
+            LayoutEntry{ offset: 0, size: 8, kind: LayoutKind::Integer },
+            LayoutEntry{ offset: 8, size: 8, kind: LayoutKind::Float },
+        ]));
+    }
+
+    #[test]
+    fn fieldless_enums(){
+        let variables = variables(OPTION_IR);
+        let enum_type = match &variables["!271"].r#type{
+            BasicTypeOrCompositeType::Enum(enum_type) => enum_type,
+            other => panic!("{:?}", other),
+        };
+        let variants: Vec<_> = enum_type.variants.iter().map(|variant| (variant.name.as_str(), variant.discriminant, variant.fields.len())).collect();
+        assert_eq!(variants, [("Up", Some(0), 0), ("Down", Some(1), 0)]);
+        assert!(matches!(enum_type.discriminant.as_ref().map(|(offset, discriminant)| (*offset, discriminant.as_ref())),
+            Some((0, BasicTypeOrCompositeType::BasicType(_)))), "{:?}", enum_type.discriminant);
+        let layout = &variables["!271"].layout;
+        assert_eq!((layout.size, layout.entries.clone()), (1, vec![LayoutEntry{ offset: 0, size: 1, kind: LayoutKind::Integer }]));
+    }
+
+    #[test]
+    fn niche_enums(){
+        let variables = variables(OPTION_IR);
+        // the null pointer is `None`, any other value is `Some`
+        for id in ["!266", "!267"]{
+            assert_eq!(enum_variants(&variables, id), (Some(0), true, vec!["None = 0: ".to_string(), "Some = niche: *f64".to_string()]), "{}", id);
+            let layout = &variables[id].layout;
+            assert_eq!(layout.size, 8);
+            assert!(matches!(layout.entries.as_slice(), [LayoutEntry{ offset: 0, size: 8, kind: LayoutKind::Pointer(_) }]), "{:?}", layout.entries);
+        }
+    }
+
+    // `fn total(shape: [usize; 2], grid: [[f64; 3]; 2])` from rustc 1.95, then the forms clang gives a C
+    // `double m[2][3]` and a `double v[n]`: one array type with a subrange per dimension, and a subrange
+    // counted by an artificial variable. Then arrays too large to expand.
+    const ARRAY_IR: &str = r#"define internal double @_ZN2ar5total17h32f119facd64d724E(ptr align 8 %shape, ptr align 8 %grid) unnamed_addr #1 !dbg !137 {
+start:
+    #dbg_declare(ptr %shape, !148, !DIExpression(), !150)
+    #dbg_declare(ptr %grid, !149, !DIExpression(), !151)
+    #dbg_declare(ptr %m, !202, !DIExpression(), !150)
+    #dbg_declare(ptr %v, !205, !DIExpression(), !150)
+    #dbg_declare(ptr %bytes, !213, !DIExpression(), !150)
+    #dbg_declare(ptr %huge, !216, !DIExpression(), !150)
+  ret double 0.0
+}
+
+!9 = !DIBasicType(name: "usize", size: 64, encoding: DW_ATE_unsigned)
+!45 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!140 = !DICompositeType(tag: DW_TAG_array_type, baseType: !9, size: 128, align: 64, elements: !141)
+!141 = !{!142}
+!142 = !DISubrange(count: 2, lowerBound: 0)
+!143 = !DICompositeType(tag: DW_TAG_array_type, baseType: !144, size: 384, align: 64, elements: !141)
+!144 = !DICompositeType(tag: DW_TAG_array_type, baseType: !45, size: 192, align: 64, elements: !145)
+!145 = !{!146}
+!146 = !DISubrange(count: 3, lowerBound: 0)
+!148 = !DILocalVariable(name: "shape", arg: 1, scope: !137, file: !34, line: 2, type: !140)
+!149 = !DILocalVariable(name: "grid", arg: 2, scope: !137, file: !34, line: 2, type: !143)
+!150 = !DILocation(line: 2, column: 14, scope: !137)
+!151 = !DILocation(line: 2, column: 32, scope: !137)
+!200 = !DICompositeType(tag: DW_TAG_array_type, baseType: !45, size: 384, align: 64, elements: !201)
+!201 = !{!142, !146}
+!202 = !DILocalVariable(name: "m", scope: !137, file: !34, line: 3, type: !200)
+!203 = !DICompositeType(tag: DW_TAG_array_type, baseType: !45, align: 64, elements: !204)
+!204 = !{!206}
+!205 = !DILocalVariable(name: "v", scope: !137, file: !34, line: 4, type: !203)
+!206 = !DISubrange(count: !207)
+!207 = !DILocalVariable(name: "__vla_expr0", scope: !137, type: !9, flags: DIFlagArtificial)
+!208 = !DIBasicType(name: "u8", size: 8, encoding: DW_ATE_unsigned)
+!209 = !DICompositeType(tag: DW_TAG_array_type, baseType: !210, size: 134217728, align: 8, elements: !211)
+!210 = !DICompositeType(tag: DW_TAG_array_type, baseType: !208, size: 32768, align: 8, elements: !211)
+!211 = !{!212}
+!212 = !DISubrange(count: 4096, lowerBound: 0)
+!213 = !DILocalVariable(name: "bytes", scope: !137, file: !34, line: 5, type: !209)
+!214 = !DICompositeType(tag: DW_TAG_array_type, baseType: !45, align: 64, elements: !215)
+!215 = !{!217, !217}
+!216 = !DILocalVariable(name: "huge", scope: !137, file: !34, line: 6, type: !214)
+!217 = !DISubrange(count: 4294967296, lowerBound: 0)
+"#;
+
+    /// The offsets in bits of the elements of an array, and what they are
+    fn array_elements(array: &BasicTypeOrCompositeType) -> Vec<(u64, String)>{
+        match array{
+            BasicTypeOrCompositeType::Array(length, stride, element) => {
+                let element = match element.as_ref(){
+                    BasicTypeOrCompositeType::BasicType(basic) => basic.original.name.clone(),
+                    BasicTypeOrCompositeType::Array(inner, _, _) => format!("[{}]", inner),
+                    other => format!("{:?}", other),
+                };
+                (0..*length).map(|index| (index * stride, element.clone())).collect()
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn array_counts(){
+        let variables = variables(ARRAY_IR);
+        let at = |offsets: &[u64], element: &str| -> Vec<(u64, String)> {
+            offsets.iter().map(|offset| (*offset, element.to_string())).collect()
+        };
+        assert_eq!(array_elements(&variables["!148"].r#type), at(&[0, 64], "usize"));
+        // nested Rust arrays
+        let grid = &variables["!149"].r#type;
+        assert_eq!(array_elements(grid), at(&[0, 192], "[3]"));
+        match grid{
+            BasicTypeOrCompositeType::Array(_, _, row) => assert_eq!(array_elements(row), at(&[0, 64, 128], "f64")),
+            other => panic!("{:?}", other),
+        }
+        // kept as one array of rows in the layout
+        match variables["!149"].layout.entries.as_slice(){
+            [LayoutEntry{ offset: 0, size: 48, kind: LayoutKind::Array(row, 2) }] => {
+                assert!(matches!(row.entries.as_slice(), [LayoutEntry{ offset: 0, size: 24, kind: LayoutKind::Array(_, 3) }]), "{:?}", row);
+            }
+            other => panic!("{:?}", other),
+        }
+        // a subrange per dimension, row-major
+        assert_eq!(array_elements(&variables["!202"].r#type), at(&[0, 64, 128, 192, 256, 320], "f64"));
+        match &variables["!205"].r#type{
+            BasicTypeOrCompositeType::UnknownLengthArray(Some(length), element) => {
+                assert_eq!(length, "!207");
+                assert!(matches!(element.as_ref(), BasicTypeOrCompositeType::BasicType(basic) if basic.original.name == "f64"));
+            }
+            other => panic!("{:?}", other),
+        }
+        // `[[u8; 4096]; 4096]` keeps one element per dimension, 2^64 elements is no length
+        match &variables["!213"].r#type{
+            BasicTypeOrCompositeType::Array(4096, 32768, row) => assert!(matches!(row.as_ref(), BasicTypeOrCompositeType::Array(4096, 8, _))),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(&variables["!216"].r#type, BasicTypeOrCompositeType::UnknownLengthArray(None, _)), "{:?}", variables["!216"].r#type);
+    }
+
+    #[test]
+    fn function_pointers(){
+        let h = &variables(FN_POINTER_IR)["!82"];
+        match &h.r#type{
+            BasicTypeOrCompositeType::Composite(_, fields) => {
+                assert!(matches!(fields.as_slice(), [BasicTypeOrCompositeType::Function, BasicTypeOrCompositeType::BasicType(_)]), "{:?}", fields);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(h.layout.entries, vec![
+            LayoutEntry{ offset: 0, size: 8, kind: LayoutKind::Pointer(Box::new(Pointee::Function)) },
+            LayoutEntry{ offset: 8, size: 8, kind: LayoutKind::Float },
+        ]);
+    }
+
+    #[test]
+    fn nodes_that_are_not_types(){
+        let ir = LLVMIRMetadata::new(FN_POINTER_IR).unwrap();
+        let mut ast = Ast{ inner: HashMap::new() };
+        for (id, variant) in [("!2", "!DIFile"), ("!16", "!DINamespace"), ("!83", "!DILexicalBlock"), ("!91", "!DILocation")]{
+            assert!(matches!(ast.get_or_parse(id, &ir.metadata), Some(TypeAST::Unsupported(found)) if found == variant), "{}", id);
+        }
+    }
+}
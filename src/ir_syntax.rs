@@ -0,0 +1,32 @@
+//! Pieces of the LLVM IR text syntax the `post` rewrites need
+
+/// Splits an argument list such as `i64 %f, %"Vec<f64, Global>"* %a)` at its top level commas,
+/// returning the byte range of each argument relative to `args` and of the closing `)`
+pub fn split_arguments(args: &str) -> Result<(Vec<(usize, usize)>, usize), String>{
+    let mut ranges = vec![];
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    let mut arg_start = 0;
+    for (index, c) in args.char_indices(){
+        match c{
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' if depth == 0 => {
+                if !args[arg_start..index].trim().is_empty(){
+                    ranges.push((arg_start, index));
+                }
+                return Ok((ranges, index));
+            }
+            ')' | ']' | '}' | '>' => {
+                depth = depth.checked_sub(1).ok_or_else(|| format!("unbalanced `{}` in argument list", c))?;
+            }
+            ',' if depth == 0 => {
+                ranges.push((arg_start, index));
+                arg_start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    Err("unterminated argument list".to_string())
+}
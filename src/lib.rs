@@ -0,0 +1,495 @@
+//! Typed access to Enzyme from Rust.
+//!
+//! Calls to `__enzyme_autodiff` and `__enzyme_fwddiff` are variadic: every argument is preceded by a
+//! magic `i128` that `post` rewrites into the Enzyme activity metadata. [`autodiff!`] and [`fwddiff!`]
+//! write those calls for you and check the arguments against the signature of the differentiated function.
+
+// the `_1`/`_2` variants are the same symbols declared with the return type Enzyme gives it
+#[allow(clashing_extern_declarations)]
+extern "C" {
+    #[doc(hidden)]
+    pub fn __enzyme_autodiff(_: usize, ...);
+    #[doc(hidden)]
+    #[link_name = "__enzyme_autodiff"]
+    pub fn __enzyme_autodiff_1(_: usize, ...) -> f64;
+    #[doc(hidden)]
+    #[link_name = "__enzyme_autodiff"]
+    pub fn __enzyme_autodiff_2(_: usize, ...) -> ActiveGradients2;
+    #[doc(hidden)]
+    pub fn __enzyme_fwddiff(_: usize, ...);
+    #[doc(hidden)]
+    #[link_name = "__enzyme_fwddiff"]
+    pub fn __enzyme_fwddiff_1(_: usize, ...) -> f64;
+    pub fn __enzyme_float(pointer: usize, size: usize);
+    pub fn __enzyme_double(pointer: usize, size: usize);
+    pub fn __enzyme_integer(pointer: usize, size: usize);
+    pub fn __enzyme_pointer(pointer: usize, size: usize);
+}
+
+/// Tells Enzyme `f` holds a float, same as [`mark_type`]
+#[inline(always)]
+pub fn mark_as_float32(f: &f32){
+    mark_type(f);
+}
+
+/// Tells Enzyme what every byte of `value` holds. `cargo enzyme` already types the arguments of the
+/// differentiated functions from their debug info, this is for memory reached some other way, such
+/// as a slice built with `from_raw_parts_mut`:
+///
+/// ```ignore
+/// let input = unsafe { std::slice::from_raw_parts_mut(input, input_len) };
+/// mark_type(input);
+/// ```
+#[inline(always)]
+pub fn mark_type<T: EnzymeType + ?Sized>(value: &T){
+    value.mark();
+}
+
+/// Types that can describe their memory to Enzyme: floats, integers, pointers, arrays and slices of
+/// them, and the structs given to [`enzyme_type!`]
+pub trait EnzymeType{
+    /// Calls the `__enzyme_*` marker of each scalar of `self` with its address, padding is left out
+    fn mark(&self);
+}
+
+macro_rules! scalar_enzyme_type {
+    ($marker:ident: $($scalar:ty),+) => {
+        $(
+            impl EnzymeType for $scalar{
+                #[inline(always)]
+                fn mark(&self){
+                    unsafe { $marker(self as *const $scalar as usize, std::mem::size_of::<$scalar>()); }
+                }
+            }
+        )+
+    };
+}
+
+scalar_enzyme_type!(__enzyme_float: f32);
+scalar_enzyme_type!(__enzyme_double: f64);
+scalar_enzyme_type!(__enzyme_integer: bool, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+// only the pointer itself: what it points to may not be valid to read
+impl<T: ?Sized> EnzymeType for *const T{
+    #[inline(always)]
+    fn mark(&self){
+        unsafe { __enzyme_pointer(self as *const *const T as usize, std::mem::size_of::<*const T>()); }
+    }
+}
+
+impl<T: ?Sized> EnzymeType for *mut T{
+    #[inline(always)]
+    fn mark(&self){
+        unsafe { __enzyme_pointer(self as *const *mut T as usize, std::mem::size_of::<*mut T>()); }
+    }
+}
+
+impl<T: ?Sized> EnzymeType for &T{
+    #[inline(always)]
+    fn mark(&self){
+        unsafe { __enzyme_pointer(self as *const &T as usize, std::mem::size_of::<&T>()); }
+    }
+}
+
+impl<T: ?Sized> EnzymeType for &mut T{
+    #[inline(always)]
+    fn mark(&self){
+        unsafe { __enzyme_pointer(self as *const &mut T as usize, std::mem::size_of::<&mut T>()); }
+    }
+}
+
+impl<T: EnzymeType> EnzymeType for [T]{
+    #[inline(always)]
+    fn mark(&self){
+        for element in self{
+            element.mark();
+        }
+    }
+}
+
+impl<T: EnzymeType, const N: usize> EnzymeType for [T; N]{
+    #[inline(always)]
+    fn mark(&self){
+        self[..].mark();
+    }
+}
+
+/// The elements of the vector, its pointer, length and capacity are described by the debug info
+impl<T: EnzymeType> EnzymeType for Vec<T>{
+    #[inline(always)]
+    fn mark(&self){
+        self[..].mark();
+    }
+}
+
+/// Implements [`EnzymeType`] for a struct by marking each of the given fields where it lies:
+///
+/// ```ignore
+/// struct Tensor{ data: Vec<f64>, shape: [usize; 2] }
+/// enzyme_type!(Tensor{ data, shape });
+/// mark_type(&tensor);
+/// ```
+#[macro_export]
+macro_rules! enzyme_type {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::EnzymeType for $name{
+            #[inline(always)]
+            fn mark(&self){
+                $( $crate::EnzymeType::mark(&self.$field); )*
+            }
+        }
+    };
+}
+
+/// Yep, magic numbers, we look for those number in the output llvm-ir and replace them with metadata
+pub const ENZYME_CONST: i128 = 1321523312;
+pub const ENZYME_DUP: i128 = 314210384213;
+/// Like [`ENZYME_DUP`] but the primal result of the differentiated function is not needed
+pub const ENZYME_DUPNONEED: i128 = 271828182845;
+/// Active scalar passed by value, its gradient is returned by the `__enzyme_autodiff` call
+pub const ENZYME_OUT: i128 = 1618033988;
+/// Must be followed by the vector width as an `usize`
+pub const ENZYME_WIDTH: i128 = 1414213562;
+/// Must be followed by the size in bytes of the allocation backing the next argument
+pub const ENZYME_ALLOCATED: i128 = 1732050807;
+
+/// Argument that is not differentiated, such as `Const(&labels)` or `Const(len)`
+pub struct Const<T>(pub T);
+
+/// Argument with a shadow of the same type: the gradient is accumulated into the shadow in reverse
+/// mode, the shadow holds the tangent in forward mode. The primal is `&T` or `&mut T`, the shadow `&mut T`.
+pub struct Duplicated<'a, P: Primal>(pub P, pub &'a mut P::Pointee);
+
+/// Like [`Duplicated`] but the primal value of this argument is not needed after the call,
+/// so Enzyme may skip computing it
+pub struct DuplicatedNoNeed<'a, P: Primal>(pub P, pub &'a mut P::Pointee);
+
+/// References that can be [`Duplicated`]
+pub trait Primal{
+    type Pointee: ?Sized;
+}
+
+impl<T: ?Sized> Primal for &T{
+    type Pointee = T;
+}
+
+impl<T: ?Sized> Primal for &mut T{
+    type Pointee = T;
+}
+
+/// Scalar passed by value whose gradient is returned by [`autodiff!`]
+pub struct Active(pub f64);
+
+/// What Enzyme returns when differentiating with respect to two [`Active`] scalars
+#[doc(hidden)]
+#[repr(C)]
+pub struct ActiveGradients2(pub f64, pub f64);
+
+#[doc(hidden)]
+pub trait IntoGradients{
+    type Gradients;
+    fn into_gradients(self) -> Self::Gradients;
+}
+
+impl IntoGradients for (){
+    type Gradients = ();
+    fn into_gradients(self){}
+}
+
+impl IntoGradients for f64{
+    type Gradients = f64;
+    fn into_gradients(self) -> f64{
+        self
+    }
+}
+
+impl IntoGradients for ActiveGradients2{
+    type Gradients = (f64, f64);
+    fn into_gradients(self) -> (f64, f64){
+        (self.0, self.1)
+    }
+}
+
+/// Return types of the functions [`fwddiff!`] differentiates: `()`, or `f64` whose tangent is returned
+#[doc(hidden)]
+pub trait FwdReturn{
+    type Tangent;
+    fn fwddiff(&self) -> unsafe extern "C" fn(usize, ...) -> Self::Tangent;
+}
+
+impl FwdReturn for Option<()>{
+    type Tangent = ();
+    fn fwddiff(&self) -> unsafe extern "C" fn(usize, ...){
+        __enzyme_fwddiff
+    }
+}
+
+impl FwdReturn for Option<f64>{
+    type Tangent = f64;
+    fn fwddiff(&self) -> unsafe extern "C" fn(usize, ...) -> f64{
+        __enzyme_fwddiff_1
+    }
+}
+
+/// Reverse mode derivative of `f` through `__enzyme_autodiff`.
+///
+/// Each argument of `f` is given with its activity, the shadow of a [`Duplicated`] argument must
+/// have the type of its primal, and the call is checked against the signature of `f`:
+///
+/// ```ignore
+/// let mut d_weights = Tensor::zeros();
+/// let d_scale: f64 = autodiff!(loss, Duplicated(&weights, &mut d_weights), Const(&input), Active(scale));
+/// ```
+///
+/// A shadow of another type than its primal is rejected:
+///
+/// ```compile_fail,E0308
+/// # use oxide_enzyme::autodiff;
+/// fn sum(input: &[f64; 4]) -> f64{ input.iter().sum() }
+/// let input = [1f64; 4];
+/// let mut d_input = [0f32; 4];
+/// autodiff!(sum, Duplicated(&input, &mut d_input));
+/// ```
+///
+/// and so are too many or too few arguments:
+///
+/// ```compile_fail,E0061
+/// # use oxide_enzyme::autodiff;
+/// fn square(x: f64) -> f64{ x * x }
+/// let _: (f64, f64) = autodiff!(square, Active(3.), Active(4.));
+/// ```
+///
+/// Returns `()`, the gradient of the single [`Active`] argument or a tuple with the gradients of two.
+///
+/// In vector mode, `width = N` comes first and every [`Duplicated`] argument takes `N` shadows,
+/// each seeded with its own direction. `f` must then return `()` and have no [`Active`] arguments:
+///
+/// ```ignore
+/// autodiff!(softmax, width = 2, Duplicated(&input, &mut d_input_0, &mut d_input_1), Duplicated(&mut output, &mut seed_0, &mut seed_1));
+/// ```
+///
+/// A [`Duplicated`] argument with another number of shadows than `N` is rejected:
+///
+/// ```compile_fail,E0080
+/// # use oxide_enzyme::autodiff;
+/// fn double(input: &[f64; 2], output: &mut [f64; 2]){ output[0] = 2. * input[0]; output[1] = 2. * input[1]; }
+/// let input = [1., 2.];
+/// let mut output = [0.; 2];
+/// let (mut d_input_0, mut d_input_1) = ([0.; 2], [0.; 2]);
+/// let mut seed_0 = [1., 0.];
+/// autodiff!(double, width = 2, Duplicated(&input, &mut d_input_0, &mut d_input_1), Duplicated(&mut output, &mut seed_0));
+/// ```
+#[macro_export]
+macro_rules! autodiff {
+    ($f:expr, width = $width:literal, $($activity:ident ( $($arg:expr),+ )),+ $(,)?) => {{
+        if false {
+            // never runs, only makes the compiler check shadows and the signature of `$f`
+            $( $crate::__vector_activity!($width, $activity $($arg),+); )+
+            let _: () = $f($( $crate::__autodiff_primal!($activity $($arg),+) ),+);
+        }
+        #[allow(unused_unsafe)]
+        unsafe {
+            $crate::__enzyme_autodiff(
+                $f as *const () as usize,
+                $crate::ENZYME_WIDTH, $width as usize,
+                $( $crate::__autodiff_marker!($activity), $($arg),+ ),+
+            )
+        }
+    }};
+    ($f:expr, $($activity:ident ( $($arg:expr),+ )),+ $(,)?) => {{
+        if false {
+            // never runs, only makes the compiler check shadows and the signature of `$f`
+            $( let _ = $crate::$activity($($arg),+); )+
+            let _ = $f($( $crate::__autodiff_primal!($activity $($arg),+) ),+);
+        }
+        #[allow(unused_unsafe)]
+        let gradients = unsafe {
+            ($crate::__autodiff_extern!([$($activity)+] []))(
+                $f as *const () as usize,
+                $( $crate::__autodiff_marker!($activity), $($arg),+ ),+
+            )
+        };
+        $crate::IntoGradients::into_gradients(gradients)
+    }};
+}
+
+/// Forward mode derivative of `f` through `__enzyme_fwddiff`.
+///
+/// The shadow of each [`Duplicated`] input holds its tangent, the shadows of the outputs receive
+/// theirs. Arguments are checked like in [`autodiff!`], there is no [`Active`] in forward mode:
+///
+/// ```ignore
+/// let mut d_output = [0.; 4];
+/// fwddiff!(softmax, Duplicated(&input, &mut d_input), Duplicated(&mut output, &mut d_output));
+/// ```
+///
+/// Returns the tangent of the result if `f` returns a `f64`.
+///
+/// `width = N` computes `N` directions at once, see [`autodiff!`]. A whole Jacobian comes out of
+/// one call when the shadows of the input are the columns of the identity, one shadow per column:
+///
+/// ```compile_fail,E0080
+/// # use oxide_enzyme::fwddiff;
+/// fn double(input: &[f64; 2], output: &mut [f64; 2]){ output[0] = 2. * input[0]; output[1] = 2. * input[1]; }
+/// let input = [1., 2.];
+/// let mut output = [0.; 2];
+/// // three columns for a width of 2
+/// let (mut d_input_0, mut d_input_1, mut d_input_2) = ([1., 0.], [0., 1.], [0., 0.]);
+/// let (mut d_output_0, mut d_output_1) = ([0.; 2], [0.; 2]);
+/// fwddiff!(double, width = 2, Duplicated(&input, &mut d_input_0, &mut d_input_1, &mut d_input_2), Duplicated(&mut output, &mut d_output_0, &mut d_output_1));
+/// ```
+#[macro_export]
+macro_rules! fwddiff {
+    ($f:expr, width = $width:literal, $($activity:ident ( $($arg:expr),+ )),+ $(,)?) => {{
+        if false {
+            // never runs, only makes the compiler check shadows and the signature of `$f`
+            $( $crate::__vector_activity!($width, $activity $($arg),+); )+
+            let _: () = $f($( $crate::__autodiff_primal!($activity $($arg),+) ),+);
+        }
+        #[allow(unused_unsafe)]
+        unsafe {
+            $crate::__enzyme_fwddiff(
+                $f as *const () as usize,
+                $crate::ENZYME_WIDTH, $width as usize,
+                $( $crate::__fwddiff_marker!($activity), $($arg),+ ),+
+            )
+        }
+    }};
+    ($f:expr, $($activity:ident ( $($arg:expr),+ )),+ $(,)?) => {{
+        let primal = if false {
+            // never runs, only makes the compiler check shadows and the signature of `$f`
+            $( let _ = $crate::$activity($($arg),+); )+
+            Some($f($( $crate::__autodiff_primal!($activity $($arg),+) ),+))
+        }else{
+            None
+        };
+        #[allow(unused_unsafe)]
+        unsafe {
+            ($crate::FwdReturn::fwddiff(&primal))(
+                $f as *const () as usize,
+                $( $crate::__fwddiff_marker!($activity), $($arg),+ ),+
+            )
+        }
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __autodiff_primal {
+    (Const $value:expr) => { $value };
+    (Duplicated $primal:expr, $($shadow:expr),+) => { $primal };
+    (DuplicatedNoNeed $primal:expr, $($shadow:expr),+) => { $primal };
+    (Active $value:expr) => { $value };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __autodiff_marker {
+    (Const) => { $crate::ENZYME_CONST };
+    (Duplicated) => { $crate::ENZYME_DUP };
+    (DuplicatedNoNeed) => { $crate::ENZYME_DUPNONEED };
+    (Active) => { $crate::ENZYME_OUT };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fwddiff_marker {
+    (Const) => { $crate::ENZYME_CONST };
+    (Duplicated) => { $crate::ENZYME_DUP };
+    (DuplicatedNoNeed) => { $crate::ENZYME_DUPNONEED };
+    (Active) => { compile_error!("forward mode has no Active arguments, pass a Duplicated with the tangent as shadow") };
+}
+
+/// Checks that each shadow of a vector mode argument matches its primal and that there are `$width` of them
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __vector_activity {
+    ($width:literal, Const $value:expr) => { let _ = $crate::Const($value); };
+    ($width:literal, Active $value:expr) => {
+        compile_error!("vector mode has no Active arguments, pass a Duplicated with one shadow per direction")
+    };
+    ($width:literal, $activity:ident $primal:expr, $($shadow:expr),+) => {
+        const _: () = assert!(
+            [$(stringify!($shadow)),+].len() == $width,
+            concat!("vector mode of width ", $width, " needs ", $width, " shadows per Duplicated argument")
+        );
+        $( let _ = $crate::$activity($primal, $shadow); )+
+    };
+}
+
+/// Picks the `__enzyme_autodiff` declaration returning as many gradients as there are [`Active`] arguments
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __autodiff_extern {
+    ([Active $($rest:ident)*] [$($active:tt)*]) => { $crate::__autodiff_extern!([$($rest)*] [$($active)* x]) };
+    ([$other:ident $($rest:ident)*] [$($active:tt)*]) => { $crate::__autodiff_extern!([$($rest)*] [$($active)*]) };
+    ([] []) => { $crate::__enzyme_autodiff };
+    ([] [x]) => { $crate::__enzyme_autodiff_1 };
+    ([] [x x]) => { $crate::__enzyme_autodiff_2 };
+    ([] [$($active:tt)*]) => { compile_error!("autodiff! supports at most two Active arguments, pass the others as Duplicated") };
+}
+
+#[cfg(test)]
+mod tests{
+    use std::process::Command;
+
+    /// Compiles `items` and `pub fn marked(value: $param){ mark_type(value) }` against this crate
+    /// and returns the `__enzyme_*` calls of its IR as `(marker, size)`, in order
+    fn marker_calls(test: &str, items: &str, param: &str) -> Vec<(String, u64)>{
+        let dir = std::env::temp_dir().join(format!("oxide-enzyme-markers-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = format!(
+            "#[path = {:?}]\n#[macro_use]\n#[allow(dead_code)]\nmod oxide_enzyme;\nuse oxide_enzyme::*;\n{}\n#[no_mangle]\npub fn marked(value: {}){{ mark_type(value); }}\n",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs"), items, param
+        );
+        std::fs::write(dir.join("markers.rs"), source).unwrap();
+        let output = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+            .args(["--edition", "2018", "--crate-type=lib", "-O", "--emit=llvm-ir", "-o"])
+            .arg(dir.join("markers.ll"))
+            .arg(dir.join("markers.rs"))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let ir = std::fs::read_to_string(dir.join("markers.ll")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let call = regex::Regex::new(r"call void @__enzyme_(\w+)\(i64 [^,]+, i64 (?:noundef )?(\d+)\)").unwrap();
+        call.captures_iter(&ir).map(|caps| (caps[1].to_string(), caps[2].parse().unwrap())).collect()
+    }
+
+    fn calls(expected: &[(&str, u64)]) -> Vec<(String, u64)>{
+        expected.iter().map(|(marker, size)| (marker.to_string(), *size)).collect()
+    }
+
+    #[test]
+    fn floats(){
+        assert_eq!(marker_calls("f32", "", "&f32"), calls(&[("float", 4)]));
+        assert_eq!(marker_calls("f64", "", "&f64"), calls(&[("double", 8)]));
+    }
+
+    #[test]
+    fn integers(){
+        assert_eq!(marker_calls("u8", "", "&u8"), calls(&[("integer", 1)]));
+        assert_eq!(marker_calls("i32", "", "&i32"), calls(&[("integer", 4)]));
+        assert_eq!(marker_calls("usize", "", "&usize"), calls(&[("integer", 8)]));
+    }
+
+    #[test]
+    fn pointers(){
+        assert_eq!(marker_calls("raw", "", "&*mut f64"), calls(&[("pointer", 8)]));
+        assert_eq!(marker_calls("reference", "", "&&f64"), calls(&[("pointer", 8)]));
+    }
+
+    #[test]
+    fn slices_mark_each_element(){
+        // a single call in the loop over the elements
+        assert_eq!(marker_calls("slice", "", "&[f64]"), calls(&[("double", 8)]));
+        assert_eq!(marker_calls("array", "", "&[f32; 2]"), calls(&[("float", 4), ("float", 4)]));
+    }
+
+    #[test]
+    fn structs_mark_each_field(){
+        let tensor = "pub struct Tensor{ data: Vec<f64>, shape: [usize; 2] }\nenzyme_type!(Tensor{ data, shape });";
+        assert_eq!(marker_calls("struct", tensor, "&Tensor"), calls(&[("double", 8), ("integer", 8), ("integer", 8)]));
+    }
+}
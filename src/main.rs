@@ -1,42 +1,26 @@
-extern {
-    fn __enzyme_autodiff(_: usize, ...);
-    fn __enzyme_float(pointer: usize, size: usize);
-}
-
-pub fn mark_as_float32(f: &f32){
-    unsafe { __enzyme_float(f as *const f32 as usize, std::mem::size_of_val(f)); }
-}
-
-/// Yep, magic numbers, we look for those number in the output llvm-ir and replace them with metadata
-const ENZYME_CONST: i128 = 1321523312;
-const ENZYME_DUP: i128 = 314210384213;
-
+use oxide_enzyme::{mark_as_float32, mark_type};
+use oxide_enzyme_macros::autodiff;
 
 /// Dummy implementation
 fn linear_layer(tensor: &Tensor, weights: &Tensor) -> Tensor{
     multiply(tensor, weights)
 }
 
-/// Dummy implementation
-fn reduce_sum_layer(tensor: &Tensor) -> f64{
-    tensor.data.iter().sum()
-}
-
 /// Dummy implementation
 fn softmax(tensor: &Tensor) -> Tensor{
     let sum_exp_all: f64 = tensor.data.iter().fold(0., |acc, d|{
         acc + d.exp()
     });
     let mut out: Vec<f64> = vec![0.; tensor.data.len()];
-    for i in 0..tensor.data.len(){
-        out[i] = tensor.data[i].exp()/sum_exp_all;
+    for (out, d) in out.iter_mut().zip(&tensor.data){
+        *out = d.exp()/sum_exp_all;
     }
     // let out: Vec<f64> = tensor.data.iter().map(|d|{
     //     d.exp()/sum_exp_all
     // }).collect();
     Tensor{
         data: out,
-        shape: tensor.shape.clone()
+        shape: tensor.shape
     }
 }
 
@@ -47,7 +31,7 @@ fn softmax(tensor: &Tensor) -> Tensor{
 fn cross_entropy(input: &Tensor, target: &Tensor) -> f64{
     let mut out = 0.;
     for i in 0..input.data.len(){
-        out = out - target.data[i]*input.data[i].log(10.);
+        out -= target.data[i]*input.data[i].log(10.);
     }
     out
 }
@@ -60,8 +44,9 @@ fn dummy_nn_with_loss(input: *mut f64, input_len: usize, linear_weights: *mut f6
     let out = dummy_nn(input, input_len, linear_weights, linear_weights_len);
 
     let target_as_slice = unsafe{
-        std::slice::from_raw_parts_mut(target, target_len as usize)
+        std::slice::from_raw_parts_mut(target, target_len)
     };
+    mark_type(target_as_slice);
     let target_vec = target_as_slice.to_vec();// crashes!
     //let mut target_vec = vec![0.; target_as_slice.len()];
     //for i in 0..target_as_slice.len(){
@@ -81,11 +66,13 @@ fn dummy_nn_with_loss(input: *mut f64, input_len: usize, linear_weights: *mut f6
 fn dummy_nn(input: *mut f64, input_len: usize, linear_weights: *mut f64, linear_weights_len: usize) -> Tensor{
 
     let input_as_slice = unsafe{
-        std::slice::from_raw_parts_mut(input, input_len as usize)
+        std::slice::from_raw_parts_mut(input, input_len)
     };
     let weights_as_slice = unsafe{
-        std::slice::from_raw_parts_mut(linear_weights, linear_weights_len as usize)
+        std::slice::from_raw_parts_mut(linear_weights, linear_weights_len)
     };
+    mark_type(input_as_slice);
+    mark_type(weights_as_slice);
     let input_vec = input_as_slice.to_vec();//vec![0.; input_as_slice.len()];
     let weight_vec = weights_as_slice.to_vec();//vec![0.; weights_as_slice.len()];
     let input_tensor = Tensor{
@@ -101,10 +88,11 @@ fn dummy_nn(input: *mut f64, input_len: usize, linear_weights: *mut f64, linear_
 }
 
 /// Dummy implementation
+#[autodiff(grad_dummy_nn_tensor, Duplicated, Duplicated)]
 fn dummy_nn_tensor(input: &Tensor, linear_weights: &Tensor) -> f64{
     // input.clone()
     // let linear_out = linear_layer(&input, &linear_weights);
-    cross_entropy(&input, linear_weights)
+    cross_entropy(input, linear_weights)
 }
 
 #[derive(Debug, Clone)]
@@ -116,7 +104,7 @@ struct Tensor{
 /// Multiplies Square 2D Tensors dummy implementation
 fn multiply(left: &Tensor, right: &Tensor) -> Tensor
 {
-    let mut res : Vec<f64> = vec![0.; (left.shape[0]*left.shape[1]) as usize];
+    let mut res : Vec<f64> = vec![0.; left.shape[0]*left.shape[1]];
     for i in 0..left.shape[0] {
         for j in 0..left.shape[0] {
             res[i*left.shape[0] + j] = 0.;
@@ -141,12 +129,9 @@ fn multiply(left: &Tensor, right: &Tensor) -> Tensor
 // }
 
 fn main() {
-    let mut input = vec![1., 2., 3., 4.];
-    let mut input_shadow = vec![1., 2., 3., 4.];
-    let mut weights = vec![1.; 4];
-    let mut weights_shadow = vec![0.; 4];
-    let mut target = vec![0., 0., 1., 0.];
-    let mut target_shadow = vec![0., 0., 1., 0.];
+    let mut input = [1., 2., 3., 4.];
+    let mut weights = [1.; 4];
+    let mut target = [0., 0., 1., 0.];
 
     let input_left_ten = Tensor{
         data: vec![1., 2., 3., 4.],
@@ -174,14 +159,12 @@ fn main() {
     mark_as_float32(&b);
     for _i in 0..5{
         println!("{:?}", dummy_nn_tensor(&input_left_ten, &input_right_ten));
-        unsafe {
-            __enzyme_autodiff(dummy_nn_tensor as usize,
-                              ENZYME_DUP, &input_left_ten, &mut input_left_ten_shadow,
-                              ENZYME_DUP, &input_right_ten, &mut input_right_ten_shadow
-            );
-        }
+        grad_dummy_nn_tensor(&input_left_ten, &mut input_left_ten_shadow,
+                             &input_right_ten, &mut input_right_ten_shadow);
 
     }
+    let loss = dummy_nn_with_loss(input.as_mut_ptr(), input.len(), weights.as_mut_ptr(), weights.len(), target.as_mut_ptr(), target.len());
+    println!("loss {:?}", loss);
 
     // Uncomment bellow for it to crash
     // let output = dummy_nn(input.as_mut_ptr(),
@@ -0,0 +1,286 @@
+//! Tokenizer for the field list of specialized metadata nodes, the part between the parentheses of
+//! `!121 = !DICompositeType(tag: DW_TAG_structure_type, name: "Vec<f64, alloc::alloc::Global>", ...)`
+
+/// Value of one `key: value` field
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue{
+    /// `"Vec<f64, alloc::alloc::Global>"`, unquoted and with escapes such as `\22` decoded
+    String(String),
+    /// `!123`
+    Reference(String),
+    /// `DISPFlagLocalToUnit | DISPFlagDefinition`, a single flag is a [`FieldValue::Word`]
+    Flags(Vec<String>),
+    /// `!DIExpression(DW_OP_LLVM_fragment, 0, 64)` as the node name and its raw argument list
+    Node(String, String),
+    /// Typed constant such as the `extraData: i64 0` of enum variants, as type and value
+    Constant(String, String),
+    Null,
+    /// Anything else: `64`, `-1`, `true`, `DW_TAG_pointer_type`, `DIFlagPublic`...
+    Word(String),
+}
+
+impl FieldValue{
+    /// The value as text, strings without their quotes and `None` for `null`
+    pub fn as_text(&self) -> Option<String>{
+        match self{
+            FieldValue::String(value) | FieldValue::Reference(value) | FieldValue::Word(value) => Some(value.clone()),
+            FieldValue::Flags(flags) => Some(flags.join(" | ")),
+            FieldValue::Node(name, arguments) => Some(format!("{}({})", name, arguments)),
+            FieldValue::Constant(value_type, value) => Some(format!("{} {}", value_type, value)),
+            FieldValue::Null => None,
+        }
+    }
+}
+
+/// Splits `tag: DW_TAG_member, name: "buf", baseType: !125, flags: DIFlagPrivate` into its fields, in order
+pub fn parse_fields(fields: &str) -> Result<Vec<(String, FieldValue)>, String>{
+    let mut tokenizer = Tokenizer{ text: fields, position: 0 };
+    let mut parsed = vec![];
+    tokenizer.skip_spaces();
+    while !tokenizer.at_end(){
+        let key = tokenizer.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if key.is_empty(){
+            return Err(tokenizer.error("expected a field name"));
+        }
+        tokenizer.skip_spaces();
+        tokenizer.expect(':')?;
+        tokenizer.skip_spaces();
+        let value = tokenizer.value()?;
+        parsed.push((key.to_string(), value));
+        tokenizer.skip_spaces();
+        if !tokenizer.at_end(){
+            tokenizer.expect(',')?;
+            tokenizer.skip_spaces();
+        }
+    }
+    Ok(parsed)
+}
+
+/// `i1`, `i64`, `i128`...
+fn is_integer_type(word: &str) -> bool{
+    word.strip_prefix('i').is_some_and(|bits| !bits.is_empty() && bits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+struct Tokenizer<'a>{
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a>{
+    fn rest(&self) -> &'a str{
+        &self.text[self.position..]
+    }
+
+    fn at_end(&self) -> bool{
+        self.position == self.text.len()
+    }
+
+    fn peek(&self) -> Option<char>{
+        self.rest().chars().next()
+    }
+
+    fn error(&self, message: &str) -> String{
+        format!("{} at byte {} of `{}`", message, self.position, self.text)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String>{
+        if self.peek() == Some(expected){
+            self.position += expected.len_utf8();
+            Ok(())
+        }else{
+            Err(self.error(&format!("expected `{}`", expected)))
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> &'a str{
+        let rest = self.rest();
+        let length = rest.find(|c| !accept(c)).unwrap_or(rest.len());
+        self.position += length;
+        &rest[..length]
+    }
+
+    fn skip_spaces(&mut self){
+        self.take_while(char::is_whitespace);
+    }
+
+    fn value(&mut self) -> Result<FieldValue, String>{
+        match self.peek(){
+            Some('"') => self.string().map(FieldValue::String),
+            Some('!') => {
+                self.position += 1;
+                match self.peek(){
+                    Some('"') => self.string().map(FieldValue::String),
+                    Some(c) if c.is_ascii_digit() => {
+                        let id = self.take_while(|c| c.is_ascii_digit());
+                        Ok(FieldValue::Reference(format!("!{}", id)))
+                    }
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                        let arguments = self.parenthesized()?;
+                        Ok(FieldValue::Node(format!("!{}", name), arguments.to_string()))
+                    }
+                    _ => Err(self.error("expected a metadata reference, string or node after `!`")),
+                }
+            }
+            Some(_) => {
+                let mut words = vec![self.word()?];
+                if is_integer_type(&words[0]){
+                    self.skip_spaces();
+                    let value = self.word()?;
+                    return Ok(FieldValue::Constant(words.remove(0), value));
+                }
+                loop{
+                    self.skip_spaces();
+                    if self.peek() != Some('|'){
+                        break;
+                    }
+                    self.position += 1;
+                    self.skip_spaces();
+                    words.push(self.word()?);
+                }
+                Ok(match words.as_slice(){
+                    [word] if word == "null" => FieldValue::Null,
+                    [_] => FieldValue::Word(words.remove(0)),
+                    _ => FieldValue::Flags(words),
+                })
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String>{
+        let word = self.take_while(|c| !c.is_whitespace() && !matches!(c, ',' | '|' | '(' | ')'));
+        if word.is_empty(){
+            Err(self.error("expected a value"))
+        }else{
+            Ok(word.to_string())
+        }
+    }
+
+    /// Reads a quoted string, LLVM escapes any byte outside printable ASCII, `"` and `\` as `\XX`
+    fn string(&mut self) -> Result<String, String>{
+        self.expect('"')?;
+        let mut bytes = vec![];
+        let mut escaped = self.rest().bytes();
+        loop{
+            match escaped.next(){
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    let digits = [escaped.next(), escaped.next()];
+                    let hex = match digits{
+                        [Some(high), Some(low)] => std::str::from_utf8(&[high, low]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                        _ => None,
+                    };
+                    match hex{
+                        Some(byte) => bytes.push(byte),
+                        None => return Err(self.error("invalid escape in string")),
+                    }
+                }
+                Some(byte) => bytes.push(byte),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        let consumed = self.rest().len() - escaped.len();
+        self.position += consumed;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Returns what is between a `(` and its matching `)`, skipping over strings
+    fn parenthesized(&mut self) -> Result<&'a str, String>{
+        self.expect('(')?;
+        let start = self.position;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        for (index, c) in self.rest().char_indices(){
+            match c{
+                '"' => in_string = !in_string,
+                _ if in_string => {}
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    self.position = start + index + 1;
+                    return Ok(&self.text[start..start + index]);
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(self.error("unbalanced `(`"))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn field<'a>(fields: &'a [(String, FieldValue)], key: &str) -> &'a FieldValue{
+        &fields.iter().find(|(name, _)| name == key).unwrap_or_else(|| panic!("no field {}", key)).1
+    }
+
+    #[test]
+    fn commas_in_names(){
+        let fields = parse_fields(r#"tag: DW_TAG_structure_type, name: "RawVec<f64, alloc::alloc::Global>", scope: !104, file: !2, size: 128, align: 64, flags: DIFlagProtected, elements: !126, templateParams: !160, identifier: "ac4d7042607b0360fbb8cdc4ffb2014e""#).unwrap();
+        assert_eq!(fields.len(), 10);
+        assert_eq!(field(&fields, "name"), &FieldValue::String("RawVec<f64, alloc::alloc::Global>".to_string()));
+        assert_eq!(field(&fields, "tag"), &FieldValue::Word("DW_TAG_structure_type".to_string()));
+        assert_eq!(field(&fields, "scope"), &FieldValue::Reference("!104".to_string()));
+        assert_eq!(field(&fields, "flags"), &FieldValue::Word("DIFlagProtected".to_string()));
+        assert_eq!(field(&fields, "identifier").as_text().unwrap(), "ac4d7042607b0360fbb8cdc4ffb2014e");
+    }
+
+    #[test]
+    fn flags(){
+        let fields = parse_fields(r#"name: "len<f64, alloc::alloc::Global>", linkageName: "_ZN5alloc3vec16Vec$LT$T$C$A$GT$3len17h81b21d5e2e3cc89eE", scope: !121, file: !308, line: 2996, type: !309, scopeLine: 2996, flags: DIFlagPrototyped, spFlags: DISPFlagLocalToUnit | DISPFlagDefinition | DISPFlagOptimized, unit: !30, templateParams: !160, declaration: !312, retainedNodes: !313"#).unwrap();
+        let flags = field(&fields, "spFlags");
+        assert_eq!(flags, &FieldValue::Flags(vec!["DISPFlagLocalToUnit".to_string(), "DISPFlagDefinition".to_string(), "DISPFlagOptimized".to_string()]));
+        assert_eq!(flags.as_text().unwrap(), "DISPFlagLocalToUnit | DISPFlagDefinition | DISPFlagOptimized");
+        assert_eq!(field(&fields, "line"), &FieldValue::Word("2996".to_string()));
+    }
+
+    #[test]
+    fn null_and_nested_nodes(){
+        let fields = parse_fields(r#"name: "std", scope: null"#).unwrap();
+        assert_eq!(field(&fields, "scope"), &FieldValue::Null);
+        assert_eq!(field(&fields, "scope").as_text(), None);
+
+        let fields = parse_fields("var: !1, expr: !DIExpression()").unwrap();
+        assert_eq!(field(&fields, "expr"), &FieldValue::Node("!DIExpression".to_string(), String::new()));
+        let fields = parse_fields("var: !235, expr: !DIExpression(DW_OP_LLVM_fragment, 64, 64)").unwrap();
+        assert_eq!(field(&fields, "expr"), &FieldValue::Node("!DIExpression".to_string(), "DW_OP_LLVM_fragment, 64, 64".to_string()));
+        assert_eq!(field(&fields, "expr").as_text().unwrap(), "!DIExpression(DW_OP_LLVM_fragment, 64, 64)");
+    }
+
+    #[test]
+    fn escaped_strings(){
+        // rustc output for a source file named `we"ird.rs`
+        let fields = parse_fields(r#"filename: "we\22ird.rs", directory: "/tmp/esc", checksumkind: CSK_MD5, checksum: "dd79bc73eddc3049ac2881f22778e3b7""#).unwrap();
+        assert_eq!(field(&fields, "filename"), &FieldValue::String("we\"ird.rs".to_string()));
+        assert_eq!(field(&fields, "checksumkind"), &FieldValue::Word("CSK_MD5".to_string()));
+        let fields = parse_fields(r#"name: "caf\C3\A9\5C""#).unwrap();
+        assert_eq!(field(&fields, "name"), &FieldValue::String("café\\".to_string()));
+    }
+
+    #[test]
+    fn negative_numbers_and_strings_with_parentheses(){
+        let fields = parse_fields(r#"name: "{closure_env#0}<()>", value: -1, isUnsigned: true"#).unwrap();
+        assert_eq!(field(&fields, "name"), &FieldValue::String("{closure_env#0}<()>".to_string()));
+        assert_eq!(field(&fields, "value"), &FieldValue::Word("-1".to_string()));
+        assert_eq!(field(&fields, "isUnsigned"), &FieldValue::Word("true".to_string()));
+    }
+
+    #[test]
+    fn typed_constants(){
+        let fields = parse_fields(r#"tag: DW_TAG_member, name: "None", scope: !195, file: !2, baseType: !198, size: 128, align: 64, extraData: i64 0"#).unwrap();
+        assert_eq!(field(&fields, "extraData"), &FieldValue::Constant("i64".to_string(), "0".to_string()));
+        assert_eq!(field(&fields, "extraData").as_text().unwrap(), "i64 0");
+        assert_eq!(field(&fields, "baseType"), &FieldValue::Reference("!198".to_string()));
+    }
+
+    #[test]
+    fn errors(){
+        assert!(parse_fields(r#"name: "unterminated"#).is_err());
+        assert!(parse_fields(r#"name: "bad\zz""#).is_err());
+        assert!(parse_fields("expr: !DIExpression(DW_OP_deref").is_err());
+        assert!(parse_fields("size 64").is_err());
+        assert!(parse_fields("size: 64 align: 64").is_err());
+    }
+}
@@ -0,0 +1,35 @@
+//! The metadata nodes of a module indexed by id, so following a reference such as `baseType: !6`
+//! does not scan every node
+use std::collections::HashMap;
+use crate::structs::LLVMDebugTypeInformation;
+
+pub struct MetadataStore{
+    /// Specialized nodes such as `!6 = !DIBasicType(...)` by id
+    nodes: HashMap<String, LLVMDebugTypeInformation>,
+    /// Tuples such as `!1488 = !{!1485, !1489}` by id
+    tuples: HashMap<String, Vec<String>>,
+}
+
+impl MetadataStore{
+    pub fn new(nodes: Vec<LLVMDebugTypeInformation>, tuples: HashMap<String, Vec<String>>) -> Self{
+        MetadataStore{
+            nodes: nodes.into_iter().map(|node| (node.location_tag.clone(), node)).collect(),
+            tuples
+        }
+    }
+
+    /// The node `!N`, `id` includes the `!`
+    pub fn get(&self, id: &str) -> Option<&LLVMDebugTypeInformation>{
+        self.nodes.get(id)
+    }
+
+    /// Every specialized node, in no particular order
+    pub fn nodes(&self) -> impl Iterator<Item = &LLVMDebugTypeInformation>{
+        self.nodes.values()
+    }
+
+    /// The ids listed by the tuple `!N`
+    pub fn tuple(&self, id: &str) -> Option<&[String]>{
+        self.tuples.get(id).map(Vec::as_slice)
+    }
+}
@@ -0,0 +1,37 @@
+//! What the pipeline needs to know about the host to load Enzyme and link the final executable
+
+pub const SUPPORTED_HOSTS: [&str; 2] = ["x86_64-apple-darwin", "x86_64-unknown-linux-gnu"];
+
+/// Plugin name and link line for one target triple
+#[derive(Debug, Clone)]
+pub struct Platform{
+    /// Extension of the Enzyme LLVM plugin built for this platform, see [`Platform::enzyme_plugin`]
+    pub plugin_extension: &'static str,
+    /// Linker flags passed before the IR when rustc's link line was not captured
+    pub link_flags: &'static [&'static str],
+    /// System libraries passed after the IR when rustc's link line was not captured
+    pub system_libs: &'static [&'static str],
+}
+
+impl Platform{
+    pub fn for_triple(triple: &str) -> Result<Self, String>{
+        match triple{
+            "x86_64-apple-darwin" => Ok(Platform{
+                plugin_extension: "dylib",
+                link_flags: &["-m64", "-Wl,-dead_strip", "-nodefaultlibs"],
+                system_libs: &["-lSystem", "-lresolv", "-lc", "-lm"],
+            }),
+            "x86_64-unknown-linux-gnu" => Ok(Platform{
+                plugin_extension: "so",
+                link_flags: &["-m64", "-Wl,--gc-sections", "-nodefaultlibs"],
+                system_libs: &["-lc", "-lm", "-ldl", "-lpthread", "-lgcc_s"],
+            }),
+            _ => Err(format!("unsupported host `{}`, supported hosts are: {}", triple, SUPPORTED_HOSTS.join(", "))),
+        }
+    }
+
+    /// File name Enzyme gives its plugin for LLVM `llvm_major`, such as `LLVMEnzyme-19.so`
+    pub fn enzyme_plugin(&self, llvm_major: u32) -> String{
+        format!("LLVMEnzyme-{}.{}", llvm_major, self.plugin_extension)
+    }
+}
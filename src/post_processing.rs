@@ -1,4 +1,27 @@
-use std::io::{Read, Write};
+use std::fmt;
+use regex::Regex;
+use crate::cli::{parse_args, read_input, write_output};
+use crate::inactive::{mark_inactive_functions, parse_inactive_config, DEFAULT_INACTIVE};
+use crate::ir_syntax::split_arguments;
+
+mod cli;
+mod inactive;
+mod ir_syntax;
+
+const USAGE: &str = "\
+Rewrites the activity markers of Enzyme calls in a LLVM IR (.ll) file into Enzyme metadata and
+registers the Rust allocator functions it uses with Enzyme. Formatting, I/O and panic functions
+(`core::fmt::*`, `std::io::*`, `core::panicking::*`, `alloc::alloc::handle_alloc_error`) are marked
+inactive.
+
+Usage: post [INPUT] [-o OUTPUT] [--check] [--inactive CONFIG]...
+
+    INPUT            IR to rewrite, `-` or nothing reads stdin
+    -o, --output     Where to write the rewritten IR, `-` or nothing writes stdout
+    --check          Write nothing, exit with 1 if there is anything to rewrite or a marker that
+                     cannot be rewritten
+    --inactive       File of more inactive functions, one path such as `my_crate::log::*` per line
+";
 
 const ENZYME_CONST_PLACEHOLDER: &str = "i128 1321523312";
 const ENZYME_CONST: &str = "metadata !\"enzyme_const\"";
@@ -6,11 +29,428 @@ const ENZYME_CONST: &str = "metadata !\"enzyme_const\"";
 const ENZYME_DUP_PLACEHOLDER: &str = "i128 314210384213";
 const ENZYME_DUP: &str = "metadata !\"enzyme_dup\"";
 
+const ENZYME_DUPNONEED_PLACEHOLDER: &str = "i128 271828182845";
+const ENZYME_DUPNONEED: &str = "metadata !\"enzyme_dupnoneed\"";
+
+const ENZYME_OUT_PLACEHOLDER: &str = "i128 1618033988";
+const ENZYME_OUT: &str = "metadata !\"enzyme_out\"";
+
+const ENZYME_WIDTH_PLACEHOLDER: &str = "i128 1414213562";
+const ENZYME_WIDTH: &str = "metadata !\"enzyme_width\"";
+
+const ENZYME_ALLOCATED_PLACEHOLDER: &str = "i128 1732050807";
+const ENZYME_ALLOCATED: &str = "metadata !\"enzyme_allocated\"";
+
+/// Every placeholder we emit from Rust and the metadata Enzyme expects in its place
+const REPLACEMENTS: [(&str, &str); 6] = [
+    (ENZYME_CONST_PLACEHOLDER, ENZYME_CONST),
+    (ENZYME_DUP_PLACEHOLDER, ENZYME_DUP),
+    (ENZYME_DUPNONEED_PLACEHOLDER, ENZYME_DUPNONEED),
+    (ENZYME_OUT_PLACEHOLDER, ENZYME_OUT),
+    (ENZYME_WIDTH_PLACEHOLDER, ENZYME_WIDTH),
+    (ENZYME_ALLOCATED_PLACEHOLDER, ENZYME_ALLOCATED),
+];
+
+/// Enzyme functions whose arguments carry activity markers, only calls to these are rewritten
+const ENZYME_ENTRY_POINTS: [&str; 2] = ["@__enzyme_autodiff(", "@__enzyme_fwddiff("];
+
+/// Something the rewriter found suspicious, reported to the user instead of silently ignored
+#[derive(Debug, Clone)]
+pub enum Diagnostic{
+    /// A marker value outside the argument list of an Enzyme call, left untouched
+    MarkerOutsideCall{ line: usize, value: String },
+    /// An Enzyme call whose arguments could not be rewritten, left untouched
+    UnrewrittenCall{ line: usize, reason: String },
+}
+
+impl fmt::Display for Diagnostic{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            Diagnostic::MarkerOutsideCall { line, value } => {
+                write!(f, "line {}: activity marker {} found outside of an Enzyme call, not rewritten", line, value)
+            }
+            Diagnostic::UnrewrittenCall { line, reason } => {
+                write!(f, "line {}: could not rewrite Enzyme call: {}", line, reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RewrittenIr{
+    pub ir: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Replaces the activity marker placeholders by their metadata, but only inside the argument
+/// list of Enzyme calls such as `call void (i64, ...) @__enzyme_autodiff(i64 %f, i128 314210384213, ...)`
+pub fn rewrite_activity_markers(ir: &str) -> RewrittenIr{
+    let marker_values: Vec<&str> = REPLACEMENTS.iter()
+        .map(|(placeholder, _)| placeholder.trim_start_matches("i128 "))
+        .collect();
+    let marker_regex = Regex::new(&format!(r"\b({})\b", marker_values.join("|"))).unwrap();
+    let mut rewritten = String::with_capacity(ir.len());
+    let mut diagnostics = vec![];
+    for (index, line) in ir.split_inclusive('\n').enumerate(){
+        let line_number = index + 1;
+        match find_enzyme_call_arguments(line){
+            Some(args_start) => match rewrite_call(line, args_start, &marker_regex){
+                Ok(new_line) => rewritten.push_str(&new_line),
+                Err(reason) => {
+                    diagnostics.push(Diagnostic::UnrewrittenCall { line: line_number, reason });
+                    rewritten.push_str(line);
+                }
+            },
+            None => {
+                for found in marker_regex.find_iter(line){
+                    diagnostics.push(Diagnostic::MarkerOutsideCall { line: line_number, value: found.as_str().to_string() });
+                }
+                rewritten.push_str(line);
+            }
+        }
+    }
+    RewrittenIr{
+        ir: rewritten,
+        diagnostics
+    }
+}
+
+/// Returns the byte index right after the `(` opening the argument list if this line is a
+/// `call` or `invoke` of one of the [`ENZYME_ENTRY_POINTS`]
+fn find_enzyme_call_arguments(line: &str) -> Option<usize>{
+    ENZYME_ENTRY_POINTS.iter().find_map(|entry_point| {
+        let position = line.find(entry_point)?;
+        let instruction = &line[..position];
+        if instruction.contains("call ") || instruction.trim_start().starts_with("invoke "){
+            Some(position + entry_point.len())
+        }else{
+            None
+        }
+    })
+}
+
+fn rewrite_call(line: &str, args_start: usize, marker_regex: &Regex) -> Result<String, String>{
+    let args = &line[args_start..];
+    let (ranges, args_end) = split_arguments(args)?;
+    let mut new_args = String::with_capacity(args_end);
+    let mut copied_until = 0;
+    for (index, &(start, end)) in ranges.iter().enumerate(){
+        let arg = args[start..end].trim();
+        // newer rustc puts parameter attributes between type and value: `i128 noundef 314210384213`
+        let arg_type = arg.split_whitespace().next().unwrap_or_default();
+        let arg_value = arg.split_whitespace().last().unwrap_or_default();
+        let marker = REPLACEMENTS.iter().find(|(placeholder, _)| placeholder.trim_start_matches("i128 ") == arg_value);
+        if let (Some((placeholder, metadata)), "i128") = (marker, arg_type){
+            if *placeholder == ENZYME_WIDTH_PLACEHOLDER{
+                let width = ranges.get(index + 1).map(|&(start, end)| args[start..end].trim());
+                if width.and_then(constant_width).is_none(){
+                    let found = width.map_or("nothing".to_string(), |width| format!("`{}`", width));
+                    return Err(format!("enzyme_width must be followed by a constant width such as `i64 4`, found {}", found));
+                }
+            }
+            let arg_offset = start + args[start..end].find(arg).unwrap();
+            new_args.push_str(&args[copied_until..arg_offset]);
+            new_args.push_str(metadata);
+            copied_until = arg_offset + arg.len();
+        }else if let Some(found) = marker_regex.find(arg){
+            return Err(format!("marker {} is passed as `{}` instead of `i128 {}`", found.as_str(), arg, found.as_str()));
+        }
+    }
+    new_args.push_str(&args[copied_until..]);
+    Ok(format!("{}{}", &line[..args_start], new_args))
+}
+
+/// Rust's global allocator functions, `__rust_alloc(size, align)`, `__rust_alloc_zeroed(size, align)`,
+/// `__rust_realloc(ptr, old_size, align, new_size)` and `__rust_dealloc(ptr, size, align)`
+const RUST_ALLOC: &str = "__rust_alloc";
+const RUST_ALLOC_ZEROED: &str = "__rust_alloc_zeroed";
+const RUST_REALLOC: &str = "__rust_realloc";
+const RUST_DEALLOC: &str = "__rust_dealloc";
+
+/// Function of the module reallocating through the registered allocator functions, which Enzyme can
+/// differentiate unlike `__rust_realloc`
+const ENZYME_RUST_REALLOC: &str = "@__enzyme_rust_realloc";
+
+/// Tells Enzyme that `__rust_alloc` and `__rust_alloc_zeroed` allocate memory freed by `__rust_dealloc`,
+/// so it can allocate the shadows of the allocations of differentiated code. Enzyme has no such
+/// registration for reallocation, calls to `__rust_realloc` go through [`ENZYME_RUST_REALLOC`] instead.
+/// Modules using none of these or already registering them are returned as is.
+pub fn register_allocators(ir: &str) -> String{
+    let symbol_regex = Regex::new(r"@([\w$.]*__rust_(?:alloc_zeroed|alloc|realloc|dealloc))\b").unwrap();
+    let used: Vec<&str> = symbol_regex.captures_iter(ir).map(|caps| caps.get(1).unwrap().as_str()).collect();
+    let allocating = [RUST_ALLOC, RUST_ALLOC_ZEROED, RUST_REALLOC].iter()
+        .any(|function| used.iter().any(|symbol| mangling_prefix(symbol, function).is_some()));
+    if !allocating || ir.contains("@__enzyme_allocation_like"){
+        return ir.to_string();
+    }
+    // the allocator functions all live in the same module, so they share their mangling
+    let prefix = used.iter()
+        .find_map(|symbol| [RUST_ALLOC_ZEROED, RUST_ALLOC, RUST_REALLOC, RUST_DEALLOC].iter().find_map(|function| mangling_prefix(symbol, function)))
+        .unwrap_or_default();
+    let symbol = |function: &str| {
+        if prefix.is_empty(){
+            format!("@{}", function)
+        }else{
+            format!("@{}{}_{}", prefix, function.len(), function)
+        }
+    };
+    let is_used = |function: &str| used.contains(&&symbol(function)[1..]);
+    let declared = |function: &str| ir.lines().any(|line| {
+        (line.starts_with("declare ") || line.starts_with("define ")) && line.contains(&format!("{}(", symbol(function)))
+    });
+    // typed pointers of older LLVMs or opaque `ptr`
+    let ptr = if ir.contains("i8* @") && !ir.contains(" ptr @"){ "i8*" }else{ "ptr" };
+    let function_pointer = |function: &str, signature: &str| {
+        if ptr == "ptr"{
+            format!("ptr {}", symbol(function))
+        }else{
+            format!("i8* bitcast ({}* {} to i8*)", signature, symbol(function))
+        }
+    };
+    let alloc_signature = format!("{} (i64, i64)", ptr);
+    let dealloc_signature = format!("void ({}, i64, i64)", ptr);
+
+    let mut lines: Vec<String> = ir.lines().map(|line| line.to_string()).collect();
+    let reallocating = is_used(RUST_REALLOC);
+    if reallocating{
+        let realloc = format!("{}(", symbol(RUST_REALLOC));
+        for line in lines.iter_mut(){
+            if line.contains(&realloc) && (line.contains("call ") || line.trim_start().starts_with("invoke ")){
+                *line = line.replace(&realloc, &format!("{}(", ENZYME_RUST_REALLOC));
+            }
+        }
+    }
+
+    let mut added = vec![String::new(), "; Rust allocator functions registered with Enzyme by `post`".to_string()];
+    for function in [RUST_ALLOC, RUST_DEALLOC]{
+        if !declared(function){
+            let signature = if function == RUST_ALLOC{ &alloc_signature }else{ &dealloc_signature };
+            let (result, params) = signature.split_once(' ').unwrap();
+            added.push(format!("declare {} {}{}", result, symbol(function), params));
+        }
+    }
+    // the index of the size argument of the allocator, the arguments of the deallocator: -1 for the
+    // allocated pointer then the size and alignment given to the allocator, and the deallocator
+    added.push("@.enzyme_rust_dealloc_arguments = private unnamed_addr constant [7 x i8] c\"-1,0,1\\00\"".to_string());
+    let dealloc_arguments = if ptr == "ptr"{
+        "ptr @.enzyme_rust_dealloc_arguments".to_string()
+    }else{
+        "i8* getelementptr inbounds ([7 x i8], [7 x i8]* @.enzyme_rust_dealloc_arguments, i64 0, i64 0)".to_string()
+    };
+    for function in [RUST_ALLOC, RUST_ALLOC_ZEROED]{
+        if function == RUST_ALLOC_ZEROED && !is_used(function){
+            continue;
+        }
+        added.push(format!(
+            "@__enzyme_allocation_like.{} = global [4 x {ptr}] [{}, {ptr} null, {}, {}]",
+            function.trim_start_matches('_'), function_pointer(function, &alloc_signature), dealloc_arguments,
+            function_pointer(RUST_DEALLOC, &dealloc_signature), ptr = ptr
+        ));
+    }
+    if reallocating{
+        let memcpy = if ptr == "ptr"{ "@llvm.memcpy.p0.p0.i64" }else{ "@llvm.memcpy.p0i8.p0i8.i64" };
+        if !lines.iter().any(|line| line.starts_with("declare ") && line.contains(&format!("{}(", memcpy))){
+            added.push(format!("declare void {}({ptr}, {ptr}, i64, i1)", memcpy, ptr = ptr));
+        }
+        // like `__rust_realloc`, the block is left untouched if the allocation fails
+        added.extend([
+            format!("define internal {ptr} {}({ptr} %ptr, i64 %old_size, i64 %align, i64 %new_size) {{", ENZYME_RUST_REALLOC, ptr = ptr),
+            "start:".to_string(),
+            format!("  %new = call {ptr} {}(i64 %new_size, i64 %align)", symbol(RUST_ALLOC), ptr = ptr),
+            format!("  %failed = icmp eq {} %new, null", ptr),
+            "  br i1 %failed, label %done, label %copy".to_string(),
+            "copy:".to_string(),
+            "  %shrinks = icmp ult i64 %new_size, %old_size".to_string(),
+            "  %size = select i1 %shrinks, i64 %new_size, i64 %old_size".to_string(),
+            format!("  call void {}({ptr} %new, {ptr} %ptr, i64 %size, i1 false)", memcpy, ptr = ptr),
+            format!("  call void {}({} %ptr, i64 %old_size, i64 %align)", symbol(RUST_DEALLOC), ptr),
+            "  br label %done".to_string(),
+            "done:".to_string(),
+            format!("  ret {} %new", ptr),
+            "}".to_string(),
+        ]);
+    }
+    lines.extend(added);
+    let mut registered = lines.join("\n");
+    registered.push('\n');
+    registered
+}
+
+/// The v0 mangling path before `function` in `symbol`, such as `_RNvCs1234_7___rustc` for
+/// `_RNvCs1234_7___rustc12___rust_alloc`, or an empty one if `symbol` is not mangled
+fn mangling_prefix(symbol: &str, function: &str) -> Option<String>{
+    if symbol == function{
+        return Some(String::new());
+    }
+    symbol.strip_suffix(&format!("{}_{}", function.len(), function))
+        .filter(|prefix| prefix.starts_with("_R"))
+        .map(|prefix| prefix.to_string())
+}
+
+/// The vector width passed after `enzyme_width`, which Enzyme needs as a non zero integer constant
+fn constant_width(arg: &str) -> Option<u64>{
+    let arg_type = arg.split_whitespace().next()?;
+    let value = arg.split_whitespace().last()?;
+    if !arg_type.starts_with('i') || arg_type[1..].parse::<u32>().is_err(){
+        return None;
+    }
+    value.parse().ok().filter(|width| *width > 0)
+}
+
+fn run() -> Result<i32, String>{
+    let args = parse_args(std::env::args(), &["--check"], &["--inactive"])?;
+    if args.help{
+        print!("{}", USAGE);
+        return Ok(0);
+    }
+    let ir = read_input(&args.input)?;
+    let rewritten = rewrite_activity_markers(&ir);
+    for diagnostic in &rewritten.diagnostics{
+        eprintln!("warning: {}: {}", args.input.display_name(), diagnostic);
+    }
+    let mut inactive: Vec<String> = DEFAULT_INACTIVE.iter().map(|path| path.to_string()).collect();
+    let mut configured = vec![];
+    for (_, config) in args.options.iter().filter(|(option, _)| *option == "--inactive"){
+        let contents = std::fs::read_to_string(config).map_err(|e| format!("could not read {}: {}", config, e))?;
+        for path in parse_inactive_config(&contents).map_err(|e| format!("{}: {}", config, e))?{
+            configured.push((config, path.clone()));
+            inactive.push(path);
+        }
+    }
+    let (output, marked) = mark_inactive_functions(&register_allocators(&rewritten.ir), &inactive);
+    for path in &marked{
+        eprintln!("note: {}: marked `{}` inactive", args.input.display_name(), path.path);
+    }
+    for (config, pattern) in &configured{
+        if !marked.iter().any(|path| path.matches(pattern)){
+            eprintln!("warning: {}: `{}` matches no function to mark inactive in {}", config, pattern, args.input.display_name());
+        }
+    }
+    if args.flag("--check"){
+        return if output == ir && rewritten.diagnostics.is_empty(){
+            Ok(0)
+        }else{
+            if output != ir{
+                eprintln!("{} has activity markers, allocators or inactive functions to rewrite", args.input.display_name());
+            }
+            Ok(1)
+        };
+    }
+    write_output(&args.output, &output)?;
+    Ok(0)
+}
+
 fn main(){
-    let mut string = String::new();
-    let mut file = std::fs::File::open("oxide_enzyme.ll").unwrap();
-    file.read_to_string(&mut string).unwrap();
-    let replaced = string.replace(ENZYME_CONST_PLACEHOLDER, ENZYME_CONST)
-        .replace(ENZYME_DUP_PLACEHOLDER, ENZYME_DUP);
-    std::fs::File::create("oxide_enzyme_replaced.ll").unwrap().write_all(replaced.as_bytes()).unwrap();
-}
\ No newline at end of file
+    match run(){
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            eprintln!("Run `post --help` for usage");
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn rewrite(line: &str) -> RewrittenIr{
+        rewrite_activity_markers(&format!("{}\n", line))
+    }
+
+    #[test]
+    fn markers_in_enzyme_calls_become_metadata(){
+        let rewritten = rewrite("  %r = call double (...) @__enzyme_autodiff(double (double*, double*)* @f, i128 1321523312, double* %x, i128 314210384213, double* %y, double* %dy, i128 271828182845, double* %z, double* %dz, i128 1618033988, double %w)");
+        assert_eq!(rewritten.ir, concat!(
+            "  %r = call double (...) @__enzyme_autodiff(double (double*, double*)* @f, metadata !\"enzyme_const\", double* %x, ",
+            "metadata !\"enzyme_dup\", double* %y, double* %dy, metadata !\"enzyme_dupnoneed\", double* %z, double* %dz, ",
+            "metadata !\"enzyme_out\", double %w)\n"
+        ));
+        assert!(rewritten.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn markers_with_parameter_attributes(){
+        let rewritten = rewrite("  invoke void (...) @__enzyme_fwddiff(ptr @f, i128 noundef 314210384213, ptr %x, ptr %dx) to label %ok unwind label %cleanup");
+        assert_eq!(rewritten.ir, "  invoke void (...) @__enzyme_fwddiff(ptr @f, metadata !\"enzyme_dup\", ptr %x, ptr %dx) to label %ok unwind label %cleanup\n");
+        assert!(rewritten.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn quoted_types_keep_their_commas(){
+        let rewritten = rewrite("  call void (...) @__enzyme_autodiff(void (%\"Pair<f64, (f64, f64)>\"*)* @f, i128 314210384213, %\"Pair<f64, (f64, f64)>\"* %p, %\"Pair<f64, (f64, f64)>\"* %dp)");
+        assert_eq!(rewritten.ir, "  call void (...) @__enzyme_autodiff(void (%\"Pair<f64, (f64, f64)>\"*)* @f, metadata !\"enzyme_dup\", %\"Pair<f64, (f64, f64)>\"* %p, %\"Pair<f64, (f64, f64)>\"* %dp)\n");
+        assert!(rewritten.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn markers_outside_calls_are_reported(){
+        let line = "  store i128 314210384213, i128* %marker";
+        let rewritten = rewrite(line);
+        assert_eq!(rewritten.ir, format!("{}\n", line));
+        assert!(matches!(rewritten.diagnostics.as_slice(), [Diagnostic::MarkerOutsideCall{ line: 1, value }] if value == "314210384213"));
+    }
+
+    #[test]
+    fn mistyped_markers_are_reported(){
+        for line in [
+            // an i128 split into two halves by the calling convention
+            "  call void (...) @__enzyme_autodiff(void (double*)* @f, i64 314210384213, i64 0, double* %x, double* %dx)",
+            "  call void (...) @__enzyme_autodiff(void (double*)* @f, i64 1321523312, double* %x)",
+        ]{
+            let rewritten = rewrite(line);
+            assert_eq!(rewritten.ir, format!("{}\n", line));
+            assert!(matches!(rewritten.diagnostics.as_slice(), [Diagnostic::UnrewrittenCall{ line: 1, .. }]), "{:?}", rewritten.diagnostics);
+        }
+    }
+
+    #[test]
+    fn allocators_are_registered_and_realloc_redirected(){
+        let ir = "\
+define ptr @grow(ptr %p) {
+  %q = call ptr @_RNvCs1_7___rustc14___rust_realloc(ptr %p, i64 8, i64 8, i64 16)
+  ret ptr %q
+}
+declare ptr @_RNvCs1_7___rustc14___rust_realloc(ptr, i64, i64, i64)
+";
+        let registered = register_allocators(ir);
+        assert!(registered.contains("  %q = call ptr @__enzyme_rust_realloc(ptr %p, i64 8, i64 8, i64 16)\n"));
+        for line in [
+            "declare ptr @_RNvCs1_7___rustc12___rust_alloc(i64, i64)",
+            "declare void @_RNvCs1_7___rustc14___rust_dealloc(ptr, i64, i64)",
+            "@__enzyme_allocation_like.rust_alloc = global [4 x ptr] [ptr @_RNvCs1_7___rustc12___rust_alloc, ptr null, ptr @.enzyme_rust_dealloc_arguments, ptr @_RNvCs1_7___rustc14___rust_dealloc]",
+            "define internal ptr @__enzyme_rust_realloc(ptr %ptr, i64 %old_size, i64 %align, i64 %new_size) {",
+            "  call void @llvm.memcpy.p0.p0.i64(ptr %new, ptr %ptr, i64 %size, i1 false)",
+        ]{
+            assert!(registered.lines().any(|registered| registered == line), "missing `{}` in\n{}", line, registered);
+        }
+        // nothing zeroed to register
+        assert!(!registered.contains("rust_alloc_zeroed"));
+        assert_eq!(register_allocators(&registered), registered);
+    }
+
+    #[test]
+    fn typed_pointers_are_cast(){
+        let ir = "\
+define i8* @zeroed() {
+  %z = call i8* @__rust_alloc_zeroed(i64 8, i64 8)
+  ret i8* %z
+}
+declare i8* @__rust_alloc_zeroed(i64, i64)
+";
+        let registered = register_allocators(ir);
+        assert!(registered.contains(concat!(
+            "@__enzyme_allocation_like.rust_alloc_zeroed = global [4 x i8*] [i8* bitcast (i8* (i64, i64)* @__rust_alloc_zeroed to i8*), ",
+            "i8* null, i8* getelementptr inbounds ([7 x i8], [7 x i8]* @.enzyme_rust_dealloc_arguments, i64 0, i64 0), ",
+            "i8* bitcast (void (i8*, i64, i64)* @__rust_dealloc to i8*)]"
+        )), "{}", registered);
+        assert!(!registered.contains("__enzyme_rust_realloc"));
+    }
+
+    #[test]
+    fn modules_without_allocations_are_left_alone(){
+        let ir = "define void @f(ptr %p) {\n  call void @__rust_dealloc(ptr %p, i64 8, i64 8)\n  ret void\n}\n";
+        assert_eq!(register_allocators(ir), ir);
+    }
+}
@@ -1,29 +1,137 @@
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
-use crate::get_rust_debug_metadata;
+use crate::metadata_store::MetadataStore;
 
 /// This corresponds to a link between the LLVM local variable and the debug information
 /// from a line such as:
 /// `call void @llvm.dbg.value(metadata %"std::fmt::Formatter"* %f, metadata !214, metadata !DIExpression()), !dbg !217`
+/// or its debug record form `#dbg_value(ptr %f, !214, !DIExpression(), !217)`
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct LLVMLocalTypeVariableDebugInfo {
-    /// In the example above: `%Tensor`
+    /// In the example above: `%"std::fmt::Formatter"*`
     pub local_var_type: String,
-    /// In the example above: `%left`
+    /// In the example above: `%f`
     pub local_var_name: String,
-    /// In the example above: `!1323`
+    /// In the example above: `!214`
     pub location_tag: String,
+    /// In the example above: [`DebugIntrinsic::Value`]
+    pub intrinsic: DebugIntrinsic,
+    /// How to get from the LLVM value to the variable, empty in the example above
+    pub expression: DIExpression,
+}
+
+/// The intrinsic (or debug record) tying a LLVM value to a variable
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum DebugIntrinsic{
+    /// `llvm.dbg.value`: the value is the variable, or a piece of it
+    Value,
+    /// `llvm.dbg.declare`: the value is the address of the variable for its whole scope
+    Declare,
+    /// `llvm.dbg.addr`: the value is the address of the variable from this point on
+    Addr,
+}
+
+impl TryFrom<&str> for DebugIntrinsic{
+    type Error = ();
+    fn try_from(intrinsic: &str) -> Result<Self, Self::Error> {
+        match intrinsic{
+            "value" => Ok(Self::Value),
+            "declare" => Ok(Self::Declare),
+            "addr" => Ok(Self::Addr),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A decoded `!DIExpression(DW_OP_plus_uconst, 8, DW_OP_deref)`
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+pub struct DIExpression{
+    pub operations: Vec<DIExpressionOperation>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum DIExpressionOperation{
+    /// `DW_OP_deref`: load from the current address
+    Deref,
+    /// `DW_OP_plus_uconst, 8`: add a constant offset in bytes
+    PlusUconst(u64),
+    /// `DW_OP_LLVM_fragment, 64, 64`: the value only describes these bits of the variable
+    Fragment{ offset_in_bits: u64, size_in_bits: u64 },
+    /// `DW_OP_stack_value`: the result is the value of the variable, not its address
+    StackValue,
+    /// Any other operation, with its operands such as `1, DW_ATE_unsigned` for `DW_OP_LLVM_convert`
+    Other(String, Vec<String>),
+}
+
+impl DIExpression{
+    /// The bits of the variable described, `None` if the whole variable is
+    pub fn fragment(&self) -> Option<(u64, u64)>{
+        self.operations.iter().find_map(|operation| match operation{
+            DIExpressionOperation::Fragment { offset_in_bits, size_in_bits } => Some((*offset_in_bits, *size_in_bits)),
+            _ => None,
+        })
+    }
+}
+
+impl TryFrom<&str> for DIExpression{
+    type Error = String;
+    /// Decodes the operations of `!DIExpression(...)`, given with or without the `!DIExpression(` `)` around them
+    fn try_from(expression: &str) -> Result<Self, Self::Error> {
+        let inner = expression.trim()
+            .strip_prefix("!DIExpression(")
+            .and_then(|inner| inner.strip_suffix(')'))
+            .unwrap_or(expression);
+        let mut raw_operations: Vec<(String, Vec<String>)> = vec![];
+        for token in inner.split(',').map(str::trim).filter(|token| !token.is_empty()){
+            if token.starts_with("DW_OP_"){
+                raw_operations.push((token.to_string(), vec![]));
+            }else{
+                raw_operations.last_mut()
+                    .ok_or_else(|| format!("operand `{}` before any operation in `{}`", token, expression))?
+                    .1.push(token.to_string());
+            }
+        }
+        let operations = raw_operations.into_iter().map(|(operation, operands)| {
+            let numbers: Option<Vec<u64>> = operands.iter().map(|operand| operand.parse().ok()).collect();
+            match (operation.as_str(), numbers.as_deref()){
+                ("DW_OP_deref", Some([])) => Ok(DIExpressionOperation::Deref),
+                ("DW_OP_plus_uconst", Some([offset])) => Ok(DIExpressionOperation::PlusUconst(*offset)),
+                ("DW_OP_LLVM_fragment", Some([offset_in_bits, size_in_bits])) => Ok(DIExpressionOperation::Fragment{
+                    offset_in_bits: *offset_in_bits,
+                    size_in_bits: *size_in_bits
+                }),
+                ("DW_OP_stack_value", Some([])) => Ok(DIExpressionOperation::StackValue),
+                ("DW_OP_deref" | "DW_OP_plus_uconst" | "DW_OP_LLVM_fragment" | "DW_OP_stack_value", _) => {
+                    Err(format!("invalid operands for {} in `{}`", operation, expression))
+                }
+                _ => Ok(DIExpressionOperation::Other(operation, operands)),
+            }
+        }).collect::<Result<_, String>>()?;
+        Ok(DIExpression{ operations })
+    }
+}
+
+/// A function passed to `__enzyme_autodiff` or `__enzyme_fwddiff`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifferentiatedFunction{
+    /// As written in the IR, such as `@loss` or `@"_ZN4main4loss17h5d0e1b9ac9a8f3c2E"`
+    pub name: String,
+    /// Id of the `DISubprogram` attached to its `define`, `None` without debug info
+    pub subprogram: Option<String>,
 }
 
 /// This corresponds to the Rust Metadata information, such as
-/// !1298 = !DIDerivedType(tag: DwTagPointerType, name: "&mut alloc::vec::Vec<f64>", baseType: !6, size: 64, align: 64, dwarfAddressSpace: 0)
+/// !1298 = !DIDerivedType(tag: DwPointerType, name: "&mut alloc::vec::Vec<f64>", baseType: !6, size: 64, align: 64, dwarfAddressSpace: 0)
 #[derive(Debug, Clone)]
 pub struct LLVMDebugTypeInformation {
     /// In the example above: `!1298`
     pub location_tag: String,
+    /// The node was declared `distinct`, as in `!1298 = distinct !DICompositeType(...)`,
+    /// so LLVM never merges it with an identical one
+    pub distinct: bool,
     /// In the example above: `!DIDerivedType`
     pub variant: String,
-    /// A HashMap of each name:value, such as `tag` -> `DwTagPointerType`
+    /// A HashMap of each name:value, such as `tag` -> `DW_TAG_pointer_type`
     pub parameters: HashMap<String, String>,
 }
 
@@ -33,37 +141,22 @@ pub struct DILocalVariable{
     pub name: Option<String>,
     pub arg: Option<String>,
     pub line: String,
-    pub r#type: LocalVariableTypes,
+    /// Id of the type node, see [`Ast::get`]
+    pub r#type: String,
     pub scope: String,
 }
 
-#[derive(Clone, Debug)]
-pub enum LocalVariableTypes{
-    DIDerivedType(DIDerivedType),
-    DIBasicType(DIBasicType),
-    DICompositeType(DICompositeType)
-}
-
-impl From<TypeAST> for LocalVariableTypes{
-    fn from(ast_type: TypeAST) -> Self {
-        match ast_type{
-            TypeAST::DerivedType(t) => LocalVariableTypes::DIDerivedType(t),
-            TypeAST::DIBasicType(t) => LocalVariableTypes::DIBasicType(t),
-            TypeAST::DICompositeType(t) => LocalVariableTypes::DICompositeType(t),
-            _ => panic!("Invalid type for local var type")
-        }
-    }
-}
-
-
 #[derive(Clone, Debug)]
 pub struct DIDerivedType{
     pub tag: String,
     pub align: String,
     pub dwarf_address_space: Option<String>,
     pub size: Option<String>,
+    /// Offset in bits of a `DW_TAG_member` in its parent
+    pub offset: Option<String>,
     pub name: Option<String>,
-    pub base_type: Box<BaseType>,
+    /// Id of the type node, see [`Ast::get`]
+    pub base_type: String,
 }
 
 #[derive(Clone, Debug)]
@@ -77,134 +170,192 @@ pub struct DIBasicType{
 pub struct DICompositeType{
     pub tag: String,
     pub identifier: Option<String>,
-    pub elements: Vec<CompositeTypeElements>, // DerivedType, DISubrange, Enumerator, CompositeType
+    /// Ids of the DerivedType, DISubrange, Enumerator and CompositeType nodes, see [`Ast::get`]
+    pub elements: Vec<String>,
     pub vtable_holder: Option<String>,
     pub flags: Option<String>,
     pub name: Option<String>,
     pub file: Option<String>,
+    pub size: Option<String>,
     pub align: String,
+    /// Id of the type of the values of a `DW_TAG_enumeration_type`
+    pub base_type: Option<String>,
+    /// The variants of a `DW_TAG_variant_part` or a `DW_TAG_enumeration_type`
+    pub enum_info: Option<EnumInfo>,
+    /// Ids of the `DITemplateTypeParameter` nodes, such as the `T` of `RawVec<T>`
+    pub template_params: Vec<String>,
 }
 
+/// How a Rust enum stores which variant it holds
 #[derive(Clone, Debug)]
-pub enum CompositeTypeElements{
-    DIDerivedType(DIDerivedType),
-    DICompositeType(DICompositeType),
-    DIEnumerator(DIEnumerator),
-    DISubrange(DISubrange)
+pub struct EnumInfo{
+    /// Id of the artificial `DW_TAG_member` holding the discriminant of a variant part, its offset
+    /// and type say where the discriminant is and how to read it. `None` for an enumeration type,
+    /// whose whole value is the discriminant.
+    pub discriminator: Option<String>,
+    pub variants: Vec<EnumVariant>,
 }
 
-impl From<TypeAST> for CompositeTypeElements{
-    fn from(ast_type: TypeAST) -> Self {
-        match ast_type{
-            TypeAST::DerivedType(t) => CompositeTypeElements::DIDerivedType(t),
-            TypeAST::DICompositeType(t) => CompositeTypeElements::DICompositeType(t),
-            TypeAST::DIEnumerator(t) => CompositeTypeElements::DIEnumerator(t),
-            TypeAST::DISubrange(t) => CompositeTypeElements::DISubrange(t),
-            _ => panic!("Invalid type for BaseType")
-        }
-    }
+#[derive(Clone, Debug)]
+pub struct EnumVariant{
+    pub name: String,
+    /// Value of the discriminant selecting this variant, `None` for the variant whose field holds the
+    /// niche: it is selected by any value the other variants don't use
+    pub discriminant: Option<i128>,
+    /// Id of the struct with the fields of this variant, laid out over the whole enum.
+    /// `None` for the variants of an enumeration type, which have no fields
+    pub fields: Option<String>,
 }
 
-
+impl EnumInfo{
+    /// Whether the discriminant is stored in unused values of a field, such as the null pointer
+    /// of `Option<&f64>`, instead of a tag of its own
+    pub fn niche(&self) -> bool{
+        self.variants.iter().any(|variant| variant.discriminant.is_none())
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct DITemplateTypeParameter{
-    name: String,
-    r#type: String,
+    pub name: String,
+    /// Id of the type node, see [`Ast::get`]
+    pub r#type: String,
 }
 
 #[derive(Clone, Debug)]
-pub struct DISubroutineType{
-    types: String
+pub struct DIEnumerator{
+    pub name: String,
+    pub value: String
 }
 
+/// One dimension of a `DW_TAG_array_type`
 #[derive(Clone, Debug)]
-pub struct DIEnumerator{
-    name: String,
-    value: String
+pub struct DISubrange{
+    /// `None` for arrays without a known length
+    pub count: Option<SubrangeCount>,
+    pub lower_bound: Option<String>
 }
 
 #[derive(Clone, Debug)]
-pub struct DISubrange{
-    count: String,
-    lower_bound: String
+pub enum SubrangeCount{
+    Constant(u64),
+    /// `count: !N`, the id of the variable holding the length
+    Variable(String),
 }
 
+impl SubrangeCount{
+    /// Parses `count: 4` or `count: !42`, `-1` is the count of arrays of unknown length
+    fn parse(count: &str) -> Option<Self>{
+        if count.starts_with('!'){
+            return Some(SubrangeCount::Variable(count.to_string()));
+        }
+        count.parse().ok().map(SubrangeCount::Constant)
+    }
+}
+
+/// A parsed node, the nodes it refers to are kept as ids so recursive types don't nest forever
 #[derive(Debug, Clone)]
 pub enum TypeAST{
     DILocalVariable(DILocalVariable),
     DerivedType(DIDerivedType),
     DIBasicType(DIBasicType),
     DICompositeType(DICompositeType),
-    DISubroutineType(DISubroutineType),
+    /// The type of a function, its parameter types are not read
+    DISubroutineType,
     DITemplateTypeParameter(DITemplateTypeParameter),
     DIEnumerator(DIEnumerator),
-    DISubrange(DISubrange)
-}
-
-#[derive(Debug, Clone)]
-pub enum BaseType{
-    DIDerivedType(DIDerivedType),
-    DIBasicType(DIBasicType),
-    DICompositeType(DICompositeType),
-    DISubroutineType(DISubroutineType),
+    DISubrange(DISubrange),
+    /// A node that describes no type, such as a `!DINamespace` or `!DIFile`, by variant
+    Unsupported(String),
 }
 
-impl From<TypeAST> for BaseType{
-    fn from(ast_type: TypeAST) -> Self {
-        match ast_type{
-            TypeAST::DerivedType(t) => BaseType::DIDerivedType(t),
-            TypeAST::DIBasicType(t) => BaseType::DIBasicType(t),
-            TypeAST::DICompositeType(t) => BaseType::DICompositeType(t),
-            TypeAST::DISubroutineType(t) => BaseType::DISubroutineType(t),
-            _ => panic!("Invalid type for BaseType")
+impl TypeAST{
+    /// Ids of the nodes needed to unroll this one
+    fn references(&self) -> Vec<String>{
+        match self{
+            TypeAST::DILocalVariable(local) => vec![local.r#type.clone()],
+            TypeAST::DerivedType(derived) => vec![derived.base_type.clone()],
+            TypeAST::DICompositeType(composite) => {
+                let enum_info = composite.enum_info.iter().flat_map(|info| {
+                    info.discriminator.iter().chain(info.variants.iter().filter_map(|variant| variant.fields.as_ref()))
+                });
+                composite.elements.iter().chain(&composite.base_type).chain(enum_info).chain(&composite.template_params).cloned().collect()
+            }
+            TypeAST::DITemplateTypeParameter(parameter) => vec![parameter.r#type.clone()],
+            _ => vec![],
         }
     }
 }
 
+/// The type graph: parsed nodes by id
 #[derive(Debug, Clone)]
 pub struct Ast{
     pub inner: HashMap<String, TypeAST>
 }
 
+impl Ast{
+    /// Parses the node `tag` and every node reachable from it that is not parsed yet, each once,
+    /// so cycles through `baseType` or `elements` are fine
+    pub fn get_or_parse(&mut self, tag: &str, store: &MetadataStore) -> Option<TypeAST>{
+        let mut pending = vec![tag.to_string()];
+        while let Some(id) = pending.pop(){
+            if self.inner.contains_key(&id){
+                continue;
+            }
+            let debug_type_info = match store.get(&id){
+                Some(debug_type_info) => debug_type_info,
+                // reported by whoever follows the reference
+                None => continue,
+            };
+            let parsed = parse_llvm_debug_type_information(debug_type_info, store);
+            pending.extend(parsed.references());
+            self.inner.insert(id, parsed);
+        }
+        self.get(tag).cloned()
+    }
+
+    /// The parsed node `tag`
+    pub fn get(&self, tag: &str) -> Option<&TypeAST>{
+        self.inner.get(tag)
+    }
+}
+
 
-pub fn parse_llvm_debug_type_information(debug_type_info: &LLVMDebugTypeInformation, full_debug_type_info: &[LLVMDebugTypeInformation], multiple_tags_tag: &HashMap<String, Vec<String>>) -> TypeAST{
-    return match debug_type_info.get_variant().expect("No Variant"){
+/// Parses `debug_type_info` alone, `store` resolves its `elements` tuple
+pub fn parse_llvm_debug_type_information(debug_type_info: &LLVMDebugTypeInformation, store: &MetadataStore) -> TypeAST{
+    let variant = match debug_type_info.get_variant(){
+        Some(variant) => variant,
+        None => return TypeAST::Unsupported(debug_type_info.variant.clone()),
+    };
+    match variant{
         Variant::LocalVariable => {
-        let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in DILocalVariable", field)).clone();
+        let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in DILocalVariable", field)).clone();
         let get_val_optional = |field: &str| debug_type_info.parameters.get(field).cloned();
-            let local_type = get_rust_debug_metadata(&get_val("type"),full_debug_type_info).expect("Invalid tag");
-            let local_var_type = LocalVariableTypes::from(parse_llvm_debug_type_information(&local_type, full_debug_type_info, multiple_tags_tag));
             TypeAST::DILocalVariable(DILocalVariable{
                 file: get_val("file"),
                 name: get_val_optional("name"),
                 arg: get_val_optional("arg"),
                 line: get_val("line"),
-                r#type: local_var_type,
+                r#type: get_val("type"),
                 scope: get_val("scope")
             })
         }
-        Variant::DISubroutineType => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in DISubroutineType", field)).clone();
-            TypeAST::DISubroutineType(DISubroutineType{
-                types: get_val("types")
-            })
-        }
+        Variant::DISubroutineType => TypeAST::DISubroutineType,
         Variant::DerivedType => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in DIDerivedType", field)).clone();
+            let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in DIDerivedType", field)).clone();
             let get_val_optional = |field: &str| debug_type_info.parameters.get(field).cloned();
-            let base_type = get_rust_debug_metadata(&get_val("baseType"),full_debug_type_info).expect("Invalid tag");
             TypeAST::DerivedType(DIDerivedType{
                 tag: get_val("tag"),
                 align: get_val("align"),
                 dwarf_address_space: get_val_optional("dwarfAddressSpace"),
                 size: get_val_optional("size"),
+                offset: get_val_optional("offset"),
                 name: get_val_optional("name"),
-                base_type: Box::new(BaseType::from(parse_llvm_debug_type_information(&base_type, full_debug_type_info, multiple_tags_tag)))
+                base_type: get_val("baseType")
             })
         }
         Variant::BasicType => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in BasicType", field)).clone();
+            let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in BasicType", field)).clone();
             let get_val_optional = |field: &str| debug_type_info.parameters.get(field).cloned();
             TypeAST::DIBasicType(DIBasicType{
                 encoding: get_val("encoding"),
@@ -213,63 +364,100 @@ pub fn parse_llvm_debug_type_information(debug_type_info: &LLVMDebugTypeInformat
             })
         }
         Variant::CompositeType => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in CompositeType", field)).clone();
+            let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in CompositeType", field)).clone();
             let get_val_optional = |field: &str| debug_type_info.parameters.get(field).cloned();
-            let els = multiple_tags_tag.get(&get_val("elements")).expect("Invalid tag for tags");
-            let mut composite_els = vec![];
-            for el in els{
-                let a = get_rust_debug_metadata(el, full_debug_type_info).expect("Invalid tag");
-                let b = parse_llvm_debug_type_information(&a, full_debug_type_info, multiple_tags_tag);
-                composite_els.push(CompositeTypeElements::from(b))
-            }
+            let els = store.tuple(&get_val("elements")).expect("Invalid tag for tags");
             // println!("{:?}", els);
+            let enum_info = match debug_type_info.get_tag(){
+                Some(Tag::VariantPart) => Some(EnumInfo{
+                    discriminator: get_val_optional("discriminator"),
+                    variants: els.iter().map(|el| variant_part_variant(el, store)).collect()
+                }),
+                Some(Tag::EnumerationType) => Some(EnumInfo{
+                    discriminator: None,
+                    variants: els.iter().map(|el| enumerator_variant(el, store)).collect()
+                }),
+                _ => None,
+            };
             TypeAST::DICompositeType(DICompositeType{
                 tag: get_val("tag"),
                 identifier: get_val_optional("identifier"),
-                elements: composite_els,
+                elements: els.to_vec(),
                 vtable_holder: get_val_optional("vtableHolder"),
                 flags: get_val_optional("flags"),
                 name: get_val_optional("name"),
                 file: get_val_optional("file"),
-                align: get_val("align")
+                size: get_val_optional("size"),
+                align: get_val("align"),
+                base_type: get_val_optional("baseType"),
+                enum_info,
+                // leaves out the `DITemplateValueParameter`s of const generics
+                template_params: get_val_optional("templateParams")
+                    .and_then(|template_params| store.tuple(&template_params))
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|parameter| matches!(store.get(parameter).and_then(|node| node.get_variant()), Some(Variant::TemplateTypeParameter)))
+                    .cloned()
+                    .collect()
             })
         }
         Variant::TemplateTypeParameter => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in CompositeType", field)).clone();
+            let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in CompositeType", field)).clone();
             TypeAST::DITemplateTypeParameter(DITemplateTypeParameter{
                 name: get_val("name"),
                 r#type: get_val("type")
             })
         }
-        Variant::Namespace => {unimplemented!()}
-        Variant::File => {unimplemented!()}
-        Variant::GlobalVariableExpression => {unimplemented!()}
         Variant::Enumerator => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in CompositeType", field)).clone();
+            let get_val = |field: &str| debug_type_info.parameters.get(field).unwrap_or_else(|| panic!("No {} in CompositeType", field)).clone();
             TypeAST::DIEnumerator(DIEnumerator{
                 name: get_val("name"),
                 value: get_val("value")
             })
         }
         Variant::DISubrange => {
-            let get_val = |field: &str| debug_type_info.parameters.get(field).expect(&format!("No {} in CompositeType", field)).clone();
+            let get_val_optional = |field: &str| debug_type_info.parameters.get(field).cloned();
             TypeAST::DISubrange(DISubrange{
-                count: get_val("count"),
-                lower_bound: get_val("lowerBound")
+                count: get_val_optional("count").and_then(|count| SubrangeCount::parse(&count)),
+                lower_bound: get_val_optional("lowerBound")
             })
         }
-        Variant::DILocation => {unimplemented!()}
-        _ => {unimplemented!()}
+        Variant::Namespace | Variant::File | Variant::GlobalVariableExpression | Variant::DILocation
+        | Variant::DILexicalBlockFile | Variant::Subprogram | Variant::CompileUnit => {
+            TypeAST::Unsupported(debug_type_info.variant.clone())
+        }
     }
 }
 
+/// Parses an integer written `7`, or `i64 7` as the `extraData` of a variant. Unsigned values
+/// above `i128::MAX` keep their bits.
+fn parse_discriminant(value: &str) -> Option<i128>{
+    let value = value.split_whitespace().last()?;
+    value.parse().ok().or_else(|| value.parse::<u128>().ok().map(|value| value as i128))
+}
 
-/// This corresponds to the Rust Metadata information, such as
-/// !1488 = !{!1485, !1489}
-#[derive(Debug, Clone)]
-pub struct MultipleTagsTag{
-    pub location_tag: String,
-    pub tags: Vec<String>
+/// The variant described by `!N = !DIDerivedType(tag: DW_TAG_member, name: "Some", baseType: !27, extraData: i64 1)`
+fn variant_part_variant(id: &str, store: &MetadataStore) -> EnumVariant{
+    let member = store.get(id).expect("Invalid tag for variant");
+    let get_val = |field: &str| member.parameters.get(field).cloned().unwrap_or_else(|| panic!("No {} in variant", field));
+    EnumVariant{
+        name: get_val("name"),
+        discriminant: member.parameters.get("extraData").map(|value| {
+            parse_discriminant(value).unwrap_or_else(|| panic!("Invalid discriminant of variant {}", id))
+        }),
+        fields: Some(get_val("baseType"))
+    }
+}
+
+/// The variant described by `!N = !DIEnumerator(name: "A", value: 0, isUnsigned: true)`
+fn enumerator_variant(id: &str, store: &MetadataStore) -> EnumVariant{
+    let enumerator = store.get(id).expect("Invalid tag for enumerator");
+    let get_val = |field: &str| enumerator.parameters.get(field).cloned().unwrap_or_else(|| panic!("No {} in DIEnumerator", field));
+    EnumVariant{
+        name: get_val("name"),
+        discriminant: Some(parse_discriminant(&get_val("value")).unwrap_or_else(|| panic!("Invalid value of enumerator {}", id))),
+        fields: None
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -287,26 +475,30 @@ pub enum Variant{
     File,
     GlobalVariableExpression,
     Enumerator,
+    Subprogram,
+    CompileUnit,
 }
 
 #[derive(Clone, Debug)]
 pub enum Tag{
-    TagPointerType,
-    TagStructureType,
-    TagMember,
-    TagVariantPart,
-    TagArrayType,
+    PointerType,
+    StructureType,
+    Member,
+    VariantPart,
+    ArrayType,
+    EnumerationType,
 }
 
 impl TryFrom<&str> for Tag{
     type Error = ();
     fn try_from(variant_str: &str) -> Result<Self, Self::Error> {
         match variant_str{
-            "DwTagPointerType" => Ok(Self::TagPointerType),
-            "DwTagStructureType" => Ok(Self::TagStructureType),
-            "DwTagMember" => Ok(Self::TagMember),
-            "DwTagVariantPart" => Ok(Self::TagVariantPart),
-            "DW_TAG_array_type" => Ok(Self::TagArrayType),
+            "DW_TAG_pointer_type" => Ok(Self::PointerType),
+            "DW_TAG_structure_type" => Ok(Self::StructureType),
+            "DW_TAG_member" => Ok(Self::Member),
+            "DW_TAG_variant_part" => Ok(Self::VariantPart),
+            "DW_TAG_array_type" => Ok(Self::ArrayType),
+            "DW_TAG_enumeration_type" => Ok(Self::EnumerationType),
             _ => Err(()),
         }
     }
@@ -329,6 +521,8 @@ impl TryFrom<&str> for Variant{
             "!DIFile" => Ok(Self::File),
             "!DIGlobalVariableExpression" => Ok(Self::GlobalVariableExpression),
             "!DIEnumerator" => Ok(Self::Enumerator),
+            "!DISubprogram" => Ok(Self::Subprogram),
+            "!DICompileUnit" => Ok(Self::CompileUnit),
             _ => Err(()),
         }
     }
@@ -339,7 +533,7 @@ impl LLVMDebugTypeInformation {
         self.variant.as_str().try_into().ok()
     }
     pub fn get_tag(&self) -> Option<Tag>{
-        self.parameters.get("tag").map(|e| e.as_str().try_into().ok()).flatten()
+        self.parameters.get("tag").and_then(|e| e.as_str().try_into().ok())
     }
 
     pub fn get_name(&self) -> Option<String>{
@@ -0,0 +1,441 @@
+//! Writes the TypeTrees recovered from the debug info into the IR, as `"enzyme_type"` attributes of
+//! the parameters of the differentiated functions and as `__enzyme_*` markers before the copies of
+//! memory they make, so Enzyme does not have to guess their types
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use regex::Regex;
+use crate::argument_type_trees;
+use crate::file_parser::{extract_llvm_to_rust_metadata, LLVMIRMetadata};
+use crate::structs::{Ast, DebugIntrinsic, DIExpressionOperation, LLVMLocalTypeVariableDebugInfo, TypeAST};
+use crate::type_layout::type_layout;
+use crate::type_tree::{ConcreteType, TypeTree};
+
+/// The rewritten IR, with what was left without a type
+pub struct Injection{
+    pub ir: String,
+    pub warnings: Vec<String>,
+}
+
+/// Adds `"enzyme_type"="{...}"` to every parameter of the differentiated functions of `ir_text`
+/// whose debug info gives its type. `ir` is the analysis of `ir_text`.
+pub fn inject_type_trees(ir_text: &str, ir: &LLVMIRMetadata) -> Result<Injection, String>{
+    let mut lines: Vec<String> = ir_text.lines().map(|line| line.to_string()).collect();
+    let bodies = function_bodies(&lines);
+    let mut warnings = vec![];
+    for (function, arguments) in argument_type_trees(ir){
+        if function.subprogram.is_none(){
+            warnings.push(format!("{}: no debug info, its parameters are left untyped", function.name));
+            continue;
+        }
+        let (define, body_end) = match bodies.get(&function.name){
+            Some(body) => *body,
+            None => {
+                warnings.push(format!("{}: not defined in this module", function.name));
+                continue;
+            }
+        };
+        let records = extract_llvm_to_rust_metadata(&lines[define..body_end].join("\n"))?;
+
+        let (params_start, params_end) = parameter_list(&lines[define], &function.name)
+            .ok_or_else(|| format!("{}: can't find the parameters of `{}`", function.name, lines[define]))?;
+        let mut params: Vec<String> = split_parameters(&lines[define][params_start..params_end]).into_iter().map(|param| param.to_string()).collect();
+        for param in params.iter_mut(){
+            let name = match param.split_whitespace().last(){
+                Some(name) if name.starts_with('%') => name.to_string(),
+                _ => continue,
+            };
+            if param.contains("\"enzyme_type\"="){
+                continue;
+            }
+            let record = records.iter().find(|record| {
+                record.local_var_name == name && arguments.iter().any(|argument| argument.location_tag == record.location_tag)
+            });
+            let tree = record.and_then(|record| {
+                let argument = arguments.iter().find(|argument| argument.location_tag == record.location_tag)?;
+                value_type_tree(&argument.type_tree, record)
+            });
+            match tree{
+                Some(tree) if !tree.mapping.is_empty() => {
+                    let at = param.len() - name.len();
+                    param.insert_str(at, &format!("\"enzyme_type\"=\"{}\" ", tree));
+                }
+                _ => warnings.push(format!("{}: no type for parameter {}", function.name, name)),
+            }
+        }
+        let line = &lines[define];
+        lines[define] = format!("{}{}{}", &line[..params_start], params.join(", "), &line[params_end..]);
+    }
+    Ok(Injection{
+        ir: join_lines(lines, ir_text),
+        warnings
+    })
+}
+
+/// Puts an `__enzyme_*` marker of the element type before both pointers of each `llvm.memcpy` and
+/// `llvm.memmove` in the functions the differentiated ones call, directly or not: Enzyme can't tell
+/// what a copy of bytes holds, such as the one of `to_vec` or `clone`. The element type is the one
+/// the debug info of the source and destination point to. `ir` is the analysis of `ir_text`.
+pub fn type_copies(ir_text: &str, ir: &LLVMIRMetadata) -> Result<Injection, String>{
+    let copy_regex = Regex::new(r#"call void (@llvm\.mem(?:cpy|move)\.[\w.]+)\("#).unwrap();
+    let mut lines: Vec<String> = ir_text.lines().map(|line| line.to_string()).collect();
+    let bodies = function_bodies(&lines);
+    let mut ast = Ast{
+        inner: HashMap::new()
+    };
+    let mut variable_trees: HashMap<String, Option<TypeTree>> = HashMap::new();
+    // markers to put before the line at each index
+    let mut insertions: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut markers: BTreeSet<&'static str> = BTreeSet::new();
+    let mut warnings = vec![];
+    for function in reachable_functions(ir, &lines, &bodies){
+        let (start, end) = bodies[&function];
+        let mut records = None;
+        for index in start..end{
+            let line = &lines[index];
+            let callee = match copy_regex.captures(line){
+                Some(caps) => caps[1].to_string(),
+                None => continue,
+            };
+            if index > 0 && lines[index - 1].contains("call void @__enzyme_"){
+                continue;
+            }
+            let args = match parameter_list(line, &callee){
+                Some((args_start, args_end)) => split_parameters(&line[args_start..args_end]),
+                None => continue,
+            };
+            if args.len() < 3{
+                continue;
+            }
+            if records.is_none(){
+                records = Some(extract_llvm_to_rust_metadata(&lines[start..end].join("\n"))?);
+            }
+            let records = records.as_ref().unwrap();
+
+            let mut elements = vec![];
+            for arg in &args[..2]{
+                let value = arg.split_whitespace().last().unwrap_or("");
+                for record in records.iter().filter(|record| record.local_var_name == value){
+                    let tree = variable_trees.entry(record.location_tag.clone())
+                        .or_insert_with(|| variable_type_tree(&mut ast, ir, &record.location_tag))
+                        .as_ref()
+                        .and_then(|tree| value_type_tree(tree, record));
+                    if let Some(element) = tree.as_ref().and_then(pointee_element){
+                        if !elements.contains(&element){
+                            elements.push(element);
+                        }
+                    }
+                }
+            }
+            // any buffer is made of integers to a `*const u8`, a more precise type wins
+            if elements.len() > 1{
+                elements.retain(|element| *element != ConcreteType::Integer);
+            }
+            let marker = match elements.as_slice(){
+                [element] => match marker(element){
+                    Some(marker) => marker,
+                    None => continue,
+                },
+                // functions without debug info, such as the ones `post` adds, leave it to Enzyme
+                [] if !lines[start].contains("!dbg ") => continue,
+                [] => {
+                    warnings.push(format!("{}: no type for the {} at line {}", function, callee, index + 1));
+                    continue;
+                }
+                _ => {
+                    let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+                    warnings.push(format!("{}: the {} at line {} copies either {}", function, callee, index + 1, elements.join(" or ")));
+                    continue;
+                }
+            };
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let size = args[2].split_whitespace().last().unwrap_or("0");
+            let inserted = insertions.entry(index).or_default();
+            for arg in &args[..2]{
+                let mut words = arg.split_whitespace();
+                let (pointer_type, value) = match (words.next(), words.last()){
+                    (Some(pointer_type), Some(value)) if value.starts_with('%') => (pointer_type, value),
+                    _ => continue,
+                };
+                let address = format!("%enzyme_copy.{}.{}", index, inserted.len());
+                inserted.push(format!("{}{} = ptrtoint {} {} to i64", indent, address, pointer_type, value));
+                inserted.push(format!("{}call void @__enzyme_{}(i64 {}, i64 {})", indent, marker, address, size));
+            }
+            markers.insert(marker);
+        }
+    }
+    for (index, inserted) in insertions.into_iter().rev(){
+        lines.splice(index..index, inserted);
+    }
+    for marker in markers{
+        let declared = lines.iter().any(|line| line.starts_with("declare ") && line.contains(&format!("@__enzyme_{}(", marker)));
+        if !declared{
+            lines.push(format!("declare void @__enzyme_{}(i64, i64)", marker));
+        }
+    }
+    Ok(Injection{
+        ir: join_lines(lines, ir_text),
+        warnings
+    })
+}
+
+fn join_lines(lines: Vec<String>, ir_text: &str) -> String{
+    let mut joined = lines.join("\n");
+    if ir_text.ends_with('\n'){
+        joined.push('\n');
+    }
+    joined
+}
+
+/// Lines of the `define` and of the closing `}` of every function of the module, by name
+fn function_bodies(lines: &[String]) -> HashMap<String, (usize, usize)>{
+    let define_regex = Regex::new(r#"^define [^@]*@("[^"]+"|[-\w.$]+)\("#).unwrap();
+    let mut bodies = HashMap::new();
+    for (index, line) in lines.iter().enumerate(){
+        if let Some(caps) = define_regex.captures(line){
+            let end = lines[index..].iter().position(|line| line == "}").map_or(lines.len(), |end| index + end);
+            bodies.insert(format!("@{}", &caps[1]), (index, end));
+        }
+    }
+    bodies
+}
+
+/// The differentiated functions of the module and every function they reference, directly or not
+fn reachable_functions(ir: &LLVMIRMetadata, lines: &[String], bodies: &HashMap<String, (usize, usize)>) -> Vec<String>{
+    let name_regex = Regex::new(r#"@("[^"]+"|[-\w.$]+)"#).unwrap();
+    let mut reached: Vec<String> = ir.differentiated_functions.iter()
+        .map(|function| function.name.clone())
+        .filter(|name| bodies.contains_key(name))
+        .collect();
+    let mut next = 0;
+    while next < reached.len(){
+        let (start, end) = bodies[&reached[next]];
+        for line in &lines[start + 1..end]{
+            for caps in name_regex.captures_iter(line){
+                let name = format!("@{}", &caps[1]);
+                if bodies.contains_key(&name) && !reached.contains(&name){
+                    reached.push(name);
+                }
+            }
+        }
+        next += 1;
+    }
+    reached
+}
+
+/// The tree of the local variable `location_tag`
+fn variable_type_tree(ast: &mut Ast, ir: &LLVMIRMetadata, location_tag: &str) -> Option<TypeTree>{
+    match ast.get_or_parse(location_tag, &ir.metadata)?{
+        TypeAST::DILocalVariable(local) => Some(TypeTree::from_layout(&type_layout(ast, &local.r#type))),
+        _ => None,
+    }
+}
+
+/// What the pointer of tree `tree` points to, if it is the same at every offset
+fn pointee_element(tree: &TypeTree) -> Option<ConcreteType>{
+    if tree.mapping.get(&vec![-1]) != Some(&ConcreteType::Pointer){
+        return None;
+    }
+    tree.mapping.get(&vec![-1, -1]).cloned()
+}
+
+/// The `__enzyme_*` function marking memory of `element`s
+fn marker(element: &ConcreteType) -> Option<&'static str>{
+    match element{
+        ConcreteType::Float(float_type) if float_type == "double" => Some("double"),
+        ConcreteType::Float(float_type) if float_type == "float" => Some("float"),
+        ConcreteType::Integer => Some("integer"),
+        ConcreteType::Pointer => Some("pointer"),
+        _ => None,
+    }
+}
+
+/// The tree of the value described by `record`, from the `tree` of the whole variable.
+/// `None` if the expression is not one a value can be typed from.
+fn value_type_tree(tree: &TypeTree, record: &LLVMLocalTypeVariableDebugInfo) -> Option<TypeTree>{
+    let mut address = record.intrinsic != DebugIntrinsic::Value;
+    let mut fragment = None;
+    for (index, operation) in record.expression.operations.iter().enumerate(){
+        match operation{
+            // the value points to the variable
+            DIExpressionOperation::Deref if index == 0 && !address => address = true,
+            DIExpressionOperation::Fragment{ offset_in_bits, size_in_bits } => fragment = Some((offset_in_bits / 8, size_in_bits / 8)),
+            DIExpressionOperation::StackValue => {}
+            _ => return None,
+        }
+    }
+    let tree = match fragment{
+        Some((offset, size)) => tree.fragment(offset, size),
+        None => tree.clone(),
+    };
+    Some(if address{
+        tree.pointer_to()
+    }else{
+        tree
+    })
+}
+
+/// Byte range of the parameters of `name` in its `define` line, without the parentheses
+fn parameter_list(define: &str, name: &str) -> Option<(usize, usize)>{
+    let start = define.find(&format!("{}(", name))? + name.len() + 1;
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    for (index, c) in define[start..].char_indices(){
+        match c{
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' if depth == 0 => return Some((start, start + index)),
+            ')' | ']' | '}' | '>' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits a parameter list at the commas outside of quotes and brackets
+fn split_parameters(params: &str) -> Vec<&str>{
+    let mut split = vec![];
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (index, c) in params.char_indices(){
+        match c{
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' | ']' | '}' | '>' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                split.push(params[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if !params[start..].trim().is_empty(){
+        split.push(params[start..].trim());
+    }
+    split
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const IR: &str = r#"define double @loss(ptr noalias noundef readonly align 8 %x.0, i64 %x.1, double %w) unnamed_addr #0 !dbg !7 {
+start:
+    #dbg_value(ptr %x.0, !12, !DIExpression(DW_OP_LLVM_fragment, 0, 64), !15)
+    #dbg_value(i64 %x.1, !12, !DIExpression(DW_OP_LLVM_fragment, 64, 64), !15)
+    #dbg_value(double %w, !13, !DIExpression(), !15)
+  ret double %w
+}
+
+define void @main() {
+  %r = call double (...) @__enzyme_autodiff(ptr @loss, ptr %a, ptr %da, i64 2, double 1.0)
+  ret void
+}
+
+!7 = distinct !DISubprogram(name: "loss", scope: !8, file: !8, line: 1, unit: !9)
+!8 = !DIFile(filename: "a.rs", directory: "/tmp")
+!9 = distinct !DICompileUnit(language: DW_LANG_Rust, file: !8, producer: "rustc", isOptimized: true, runtimeVersion: 0, emissionKind: FullDebug)
+!10 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!11 = !DIBasicType(name: "usize", size: 64, encoding: DW_ATE_unsigned)
+!12 = !DILocalVariable(name: "x", arg: 1, scope: !7, file: !8, line: 1, type: !16)
+!13 = !DILocalVariable(name: "w", arg: 2, scope: !7, file: !8, line: 1, type: !10)
+!15 = !DILocation(line: 1, scope: !7)
+!16 = !DICompositeType(tag: DW_TAG_structure_type, name: "&[f64]", file: !8, size: 128, align: 64, elements: !17, identifier: "slice")
+!17 = !{!18, !20}
+!18 = !DIDerivedType(tag: DW_TAG_member, name: "data_ptr", scope: !16, file: !8, baseType: !19, size: 64, align: 64)
+!19 = !DIDerivedType(tag: DW_TAG_pointer_type, baseType: !10, size: 64, align: 64, dwarfAddressSpace: 0)
+!20 = !DIDerivedType(tag: DW_TAG_member, name: "length", scope: !16, file: !8, baseType: !11, size: 64, align: 64, offset: 64)
+"#;
+
+    #[test]
+    fn parameters_get_their_type_trees(){
+        let injection = inject_type_trees(IR, &LLVMIRMetadata::new(IR).unwrap()).unwrap();
+        assert_eq!(injection.ir.lines().next().unwrap(), concat!(
+            r#"define double @loss(ptr noalias noundef readonly align 8 "enzyme_type"="{[-1]:Pointer, [-1,-1]:Float@double}" %x.0, "#,
+            r#"i64 "enzyme_type"="{[-1]:Integer}" %x.1, double "enzyme_type"="{[-1]:Float@double}" %w) unnamed_addr #0 !dbg !7 {"#
+        ));
+        assert!(injection.warnings.is_empty(), "{:?}", injection.warnings);
+        // typed parameters are left alone
+        let again = inject_type_trees(&injection.ir, &LLVMIRMetadata::new(&injection.ir).unwrap()).unwrap();
+        assert_eq!(again.ir, injection.ir);
+    }
+
+    #[test]
+    fn parameters_without_debug_info_are_reported(){
+        let ir = IR.replace("    #dbg_value(double %w, !13, !DIExpression(), !15)\n", "");
+        let injection = inject_type_trees(&ir, &LLVMIRMetadata::new(&ir).unwrap()).unwrap();
+        assert!(injection.ir.contains("double %w)"));
+        assert_eq!(injection.warnings, vec!["@loss: no type for parameter %w".to_string()]);
+    }
+
+    const COPY_IR: &str = r#"define double @loss(ptr %x.0, i64 %x.1) !dbg !7 {
+  %v = call ptr @to_vec(ptr %x.0, i64 %x.1)
+  ret double 0.0
+}
+
+define ptr @to_vec(ptr %src, i64 %bytes) !dbg !20 {
+  %dst = call ptr @__rust_alloc(i64 %bytes, i64 8)
+    #dbg_value(ptr %src, !21, !DIExpression(), !15)
+    #dbg_value(ptr %dst, !22, !DIExpression(), !15)
+    #dbg_value(ptr %dst, !23, !DIExpression(), !15)
+  call void @llvm.memcpy.p0.p0.i64(ptr align 8 %dst, ptr align 8 %src, i64 %bytes, i1 false)
+  ret ptr %dst
+}
+
+define void @not_differentiated(ptr %dst, ptr %src) {
+  call void @llvm.memmove.p0.p0.i64(ptr %dst, ptr %src, i64 8, i1 false)
+  ret void
+}
+
+define void @main() {
+  %r = call double (...) @__enzyme_autodiff(ptr @loss, ptr %a, ptr %da, i64 2)
+  ret void
+}
+
+declare void @llvm.memcpy.p0.p0.i64(ptr, ptr, i64, i1)
+declare void @llvm.memmove.p0.p0.i64(ptr, ptr, i64, i1)
+
+!7 = distinct !DISubprogram(name: "loss", scope: !8, file: !8, line: 1, unit: !9)
+!8 = !DIFile(filename: "a.rs", directory: "/tmp")
+!9 = distinct !DICompileUnit(language: DW_LANG_Rust, file: !8, producer: "rustc", isOptimized: true, runtimeVersion: 0, emissionKind: FullDebug)
+!10 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!11 = !DIBasicType(name: "u8", size: 8, encoding: DW_ATE_unsigned)
+!15 = !DILocation(line: 1, scope: !20)
+!20 = distinct !DISubprogram(name: "to_vec", scope: !8, file: !8, line: 2, unit: !9)
+!21 = !DILocalVariable(name: "src", arg: 1, scope: !20, file: !8, line: 2, type: !24)
+!22 = !DILocalVariable(name: "dst", scope: !20, file: !8, line: 2, type: !25)
+!23 = !DILocalVariable(name: "bytes", scope: !20, file: !8, line: 2, type: !26)
+!24 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*const f64", baseType: !10, size: 64, align: 64, dwarfAddressSpace: 0)
+!25 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*mut f64", baseType: !10, size: 64, align: 64, dwarfAddressSpace: 0)
+!26 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*mut u8", baseType: !11, size: 64, align: 64, dwarfAddressSpace: 0)
+"#;
+
+    #[test]
+    fn copies_reached_from_differentiated_functions_get_markers(){
+        let typed = type_copies(COPY_IR, &LLVMIRMetadata::new(COPY_IR).unwrap()).unwrap();
+        let lines: Vec<&str> = typed.ir.lines().collect();
+        let copy = lines.iter().position(|line| line.contains("@llvm.memcpy.p0.p0.i64(ptr align 8")).unwrap();
+        // the bytes of `*mut u8` give way to the `f64` of the other pointers
+        assert_eq!(lines[copy - 4..copy], [
+            "  %enzyme_copy.10.0 = ptrtoint ptr %dst to i64",
+            "  call void @__enzyme_double(i64 %enzyme_copy.10.0, i64 %bytes)",
+            "  %enzyme_copy.10.2 = ptrtoint ptr %src to i64",
+            "  call void @__enzyme_double(i64 %enzyme_copy.10.2, i64 %bytes)",
+        ]);
+        let memmove = lines.iter().position(|line| line.contains("call void @llvm.memmove")).unwrap();
+        assert_eq!(lines[memmove - 1], "define void @not_differentiated(ptr %dst, ptr %src) {");
+        assert_eq!(lines.last(), Some(&"declare void @__enzyme_double(i64, i64)"));
+        assert!(typed.warnings.is_empty(), "{:?}", typed.warnings);
+        // copies already typed are left alone
+        let again = type_copies(&typed.ir, &LLVMIRMetadata::new(&typed.ir).unwrap()).unwrap();
+        assert_eq!(again.ir, typed.ir);
+    }
+
+    #[test]
+    fn parameter_lists_split_outside_brackets(){
+        let define = r#"define void @"f<(a, b)>"({ i64, i64 } %p, ptr dereferenceable(8) %q)"#;
+        let (start, end) = parameter_list(define, r#"@"f<(a, b)>""#).unwrap();
+        assert_eq!(split_parameters(&define[start..end]), vec!["{ i64, i64 } %p", "ptr dereferenceable(8) %q"]);
+    }
+}
@@ -0,0 +1,378 @@
+//! Which bytes of a type hold floats, integers and pointers, as Enzyme needs to know to
+//! differentiate through memory
+use crate::structs::*;
+use crate::{parse_bits, type_size};
+
+/// The flat layout of a type, offsets and sizes are in bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout{
+    pub size: u64,
+    pub align: u64,
+    /// Sorted by offset. The fields of the variants of an enum overlap, identical ones are kept once.
+    pub entries: Vec<LayoutEntry>,
+    /// `(offset, size)` of the bytes no entry covers
+    pub padding: Vec<(u64, u64)>,
+    /// What does not add up, such as a member past the end of its struct or at an unaligned offset
+    pub problems: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutEntry{
+    pub offset: u64,
+    pub size: u64,
+    pub kind: LayoutKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayoutKind{
+    Float,
+    Integer,
+    Pointer(Box<Pointee>),
+    /// That many elements laid out as the layout, one every `size` bytes of it. Kept whole so a
+    /// `[u8; 1 << 20]` stays a single entry.
+    Array(Box<Layout>, u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pointee{
+    Layout(Layout),
+    /// A type already being laid out behind another pointer, such as the next node of a linked
+    /// list, by name or id
+    Recursive(String),
+    Function,
+}
+
+/// The layout of the type `id`
+pub fn type_layout(ast: &Ast, id: &str) -> Layout{
+    layout(ast, id, &mut vec![id.to_string()])
+}
+
+/// `pointees` are the ids of the types behind the pointers followed to get to `id`
+fn layout(ast: &Ast, id: &str, pointees: &mut Vec<String>) -> Layout{
+    let mut builder = LayoutBuilder{
+        entries: vec![],
+        problems: vec![],
+        erased_element: None,
+    };
+    builder.add(ast, id, 0, pointees);
+    let LayoutBuilder{ mut entries, mut problems, .. } = builder;
+    entries.sort_by_key(|entry| entry.offset);
+    entries.dedup();
+
+    let size = type_size(ast, id).unwrap_or_else(|| {
+        problems.push(format!("no size for {}", id));
+        entries.iter().map(|entry| entry.offset + entry.size).max().unwrap_or(0) * 8
+    }) / 8;
+    let align = type_align(ast, id).map(|align| align / 8).filter(|align| *align > 0).unwrap_or(1);
+    if !size.is_multiple_of(align){
+        problems.push(format!("{} is {} bytes, not a multiple of its alignment {}", id, size, align));
+    }
+
+    let mut padding = vec![];
+    let mut covered = 0;
+    for entry in &entries{
+        if entry.offset + entry.size > size{
+            problems.push(format!("{} bytes at {} are past the end of the {} bytes of {}", entry.size, entry.offset, size, id));
+        }
+        if entry.offset > covered{
+            padding.push((covered, entry.offset - covered));
+        }
+        covered = covered.max(entry.offset + entry.size);
+    }
+    if size > covered{
+        padding.push((covered, size - covered));
+    }
+    Layout{
+        size,
+        align,
+        entries,
+        padding,
+        problems
+    }
+}
+
+/// Alignment in bits of the type `id`
+fn type_align(ast: &Ast, id: &str) -> Option<u64>{
+    match ast.get(id)?{
+        TypeAST::DIBasicType(basic) => parse_bits(&basic.size),
+        TypeAST::DerivedType(derived) => derived.align.parse().ok().filter(|align| *align > 0).or_else(|| type_align(ast, &derived.base_type)),
+        TypeAST::DICompositeType(composite) => composite.align.parse().ok(),
+        _ => None,
+    }
+}
+
+fn type_name(ast: &Ast, id: &str) -> String{
+    match ast.get(id){
+        Some(TypeAST::DerivedType(derived)) => derived.name.clone(),
+        Some(TypeAST::DIBasicType(basic)) => Some(basic.name.clone()),
+        Some(TypeAST::DICompositeType(composite)) => composite.name.clone(),
+        _ => None,
+    }.unwrap_or_else(|| id.to_string())
+}
+
+struct LayoutBuilder{
+    entries: Vec<LayoutEntry>,
+    problems: Vec<String>,
+    /// The `T` of the `RawVec<T>` being laid out: its buffer is a `*const u8` in the debug info
+    erased_element: Option<String>,
+}
+
+/// Id of the type given to the template parameter `name` of `composite`
+fn template_param(ast: &Ast, composite: &DICompositeType, name: &str) -> Option<String>{
+    composite.template_params.iter().find_map(|parameter| match ast.get(parameter){
+        Some(TypeAST::DITemplateTypeParameter(parameter)) if parameter.name == name => Some(parameter.r#type.clone()),
+        _ => None,
+    })
+}
+
+fn is_byte(ast: &Ast, id: &str) -> bool{
+    matches!(ast.get(id), Some(TypeAST::DIBasicType(basic)) if basic.name == "u8")
+}
+
+impl LayoutBuilder{
+    fn push(&mut self, id: &str, offset_in_bits: u64, size_in_bits: Option<u64>, kind: LayoutKind){
+        let size_in_bits = match size_in_bits{
+            Some(size_in_bits) => size_in_bits,
+            None => return self.problems.push(format!("no size for {}", id)),
+        };
+        if !offset_in_bits.is_multiple_of(8) || !size_in_bits.is_multiple_of(8){
+            return self.problems.push(format!("{} is a bit field at bit {}", id, offset_in_bits));
+        }
+        self.entries.push(LayoutEntry{
+            offset: offset_in_bits / 8,
+            size: size_in_bits / 8,
+            kind
+        });
+    }
+
+    /// Adds the entries of the type `id` placed `offset_in_bits` into the laid out type
+    fn add(&mut self, ast: &Ast, id: &str, offset_in_bits: u64, pointees: &mut Vec<String>){
+        self.add_in(ast, id, None, offset_in_bits, pointees)
+    }
+
+    /// [`LayoutBuilder::add`] for a member of the composite `parent`
+    fn add_in(&mut self, ast: &Ast, id: &str, parent: Option<&str>, offset_in_bits: u64, pointees: &mut Vec<String>){
+        let node = match ast.get(id){
+            Some(node) => node,
+            None => return self.problems.push(format!("no type {}", id)),
+        };
+        match node{
+            TypeAST::DIBasicType(basic) => {
+                let kind = match basic.encoding.as_str(){
+                    "DW_ATE_float" => LayoutKind::Float,
+                    _ => LayoutKind::Integer,
+                };
+                self.push(id, offset_in_bits, parse_bits(&basic.size), kind);
+            }
+            TypeAST::DerivedType(derived) => match derived.tag.as_str(){
+                "DW_TAG_pointer_type" | "DW_TAG_reference_type" | "DW_TAG_rvalue_reference_type" => {
+                    let pointee = match &self.erased_element{
+                        Some(element) if is_byte(ast, &derived.base_type) => pointee(ast, &element.clone(), pointees),
+                        _ => pointee(ast, &derived.base_type, pointees),
+                    };
+                    self.push(id, offset_in_bits, parse_bits(&derived.size), LayoutKind::Pointer(Box::new(pointee)));
+                }
+                "DW_TAG_member" => {
+                    let member_offset = parse_bits(&derived.offset).unwrap_or(0);
+                    match derived.align.parse::<u64>(){
+                        Ok(align) if align > 0 && !member_offset.is_multiple_of(align) => {
+                            self.problems.push(format!(
+                                "member {} of {} is at bit {}, not aligned to {} bits",
+                                derived.name.as_deref().unwrap_or(id), parent.unwrap_or("?"), member_offset, align
+                            ));
+                        }
+                        _ => {}
+                    }
+                    self.add(ast, &derived.base_type, offset_in_bits + member_offset, pointees);
+                }
+                // typedefs, const and the like share the layout of their base type
+                _ => self.add(ast, &derived.base_type, offset_in_bits, pointees),
+            }
+            TypeAST::DICompositeType(composite) => self.add_composite(ast, id, composite, offset_in_bits, pointees),
+            _ => self.problems.push(format!("{} is not a type with a layout", id)),
+        }
+    }
+
+    fn add_composite(&mut self, ast: &Ast, id: &str, composite: &DICompositeType, offset_in_bits: u64, pointees: &mut Vec<String>){
+        if composite.tag == "DW_TAG_array_type"{
+            let element_type = match &composite.base_type{
+                Some(element_type) => element_type,
+                None => return self.problems.push(format!("no element type for array {}", id)),
+            };
+            let mut length = 1u64;
+            for el in &composite.elements{
+                let count = match ast.get(el){
+                    Some(TypeAST::DISubrange(DISubrange{count: Some(SubrangeCount::Constant(count)), ..})) => *count,
+                    _ => return self.problems.push(format!("array {} has no constant length", id)),
+                };
+                length = match length.checked_mul(count){
+                    Some(length) => length,
+                    None => return self.problems.push(format!("array {} has more elements than fit in memory", id)),
+                };
+            }
+            let element = layout(ast, element_type, pointees);
+            self.problems.extend(element.problems.iter().cloned());
+            let size = match (element.size * 8).checked_mul(length){
+                Some(size) => size,
+                None => return self.problems.push(format!("array {} is larger than fits in memory", id)),
+            };
+            return self.push(id, offset_in_bits, Some(size), LayoutKind::Array(Box::new(element), length));
+        }
+        if composite.name.as_deref().is_some_and(|name| name.starts_with("RawVec<")){
+            let element = template_param(ast, composite, "T");
+            let outer = std::mem::replace(&mut self.erased_element, element);
+            self.add_members(ast, id, composite, offset_in_bits, pointees);
+            self.erased_element = outer;
+            return;
+        }
+        if let Some(enum_info) = &composite.enum_info{
+            match (&enum_info.discriminator, &composite.base_type){
+                // a niche is already covered by the field holding it
+                (Some(discriminator), _) if !enum_info.niche() => self.add_in(ast, discriminator, Some(id), offset_in_bits, pointees),
+                (None, Some(base_type)) => self.add(ast, base_type, offset_in_bits, pointees),
+                _ => {}
+            }
+        }
+        self.add_members(ast, id, composite, offset_in_bits, pointees);
+    }
+
+    /// Adds the members of the composite `id`
+    fn add_members(&mut self, ast: &Ast, id: &str, composite: &DICompositeType, offset_in_bits: u64, pointees: &mut Vec<String>){
+        for el in &composite.elements{
+            match ast.get(el){
+                Some(TypeAST::DerivedType(_)) | Some(TypeAST::DICompositeType(_)) => self.add_in(ast, el, Some(id), offset_in_bits, pointees),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn pointee(ast: &Ast, id: &str, pointees: &mut Vec<String>) -> Pointee{
+    if let Some(TypeAST::DISubroutineType) = ast.get(id){
+        return Pointee::Function;
+    }
+    if pointees.iter().any(|pointee| pointee == id){
+        return Pointee::Recursive(type_name(ast, id));
+    }
+    pointees.push(id.to_string());
+    let layout = layout(ast, id, pointees);
+    pointees.pop();
+    Pointee::Layout(layout)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::collections::HashMap;
+    use crate::file_parser::LLVMIRMetadata;
+
+    const NODES: &str = r#"!1 = !DIBasicType(name: "u8", size: 8, encoding: DW_ATE_unsigned)
+!2 = !DIBasicType(name: "f64", size: 64, encoding: DW_ATE_float)
+!3 = !DIBasicType(name: "usize", size: 64, encoding: DW_ATE_unsigned)
+!4 = !{}
+!10 = !DICompositeType(tag: DW_TAG_structure_type, name: "Padded", size: 128, align: 64, elements: !11, identifier: "padded")
+!11 = !{!12, !13}
+!12 = !DIDerivedType(tag: DW_TAG_member, name: "flag", scope: !10, baseType: !1, size: 8, align: 8)
+!13 = !DIDerivedType(tag: DW_TAG_member, name: "value", scope: !10, baseType: !2, size: 64, align: 64, offset: 64)
+!20 = !DICompositeType(tag: DW_TAG_structure_type, name: "Broken", size: 72, align: 64, elements: !21, identifier: "broken")
+!21 = !{!22, !23}
+!22 = !DIDerivedType(tag: DW_TAG_member, name: "unaligned", scope: !20, baseType: !2, size: 64, align: 64, offset: 32)
+!23 = !DIDerivedType(tag: DW_TAG_member, name: "outside", scope: !20, baseType: !3, size: 64, align: 64, offset: 128)
+!30 = !DICompositeType(tag: DW_TAG_structure_type, name: "Node", size: 128, align: 64, elements: !31, identifier: "node")
+!31 = !{!32, !33}
+!32 = !DIDerivedType(tag: DW_TAG_member, name: "value", scope: !30, baseType: !2, size: 64, align: 64)
+!33 = !DIDerivedType(tag: DW_TAG_member, name: "next", scope: !30, baseType: !34, size: 64, align: 64, offset: 64)
+!34 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*const Node", baseType: !30, size: 64, align: 64, dwarfAddressSpace: 0)
+!35 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "&Node", baseType: !30, size: 64, align: 64, dwarfAddressSpace: 0)
+!40 = !DICompositeType(tag: DW_TAG_structure_type, name: "RawVec<f64, alloc::alloc::Global>", size: 128, align: 64, elements: !41, templateParams: !45, identifier: "rawvec")
+!41 = !{!42, !43}
+!42 = !DIDerivedType(tag: DW_TAG_member, name: "ptr", scope: !40, baseType: !44, size: 64, align: 64)
+!43 = !DIDerivedType(tag: DW_TAG_member, name: "cap", scope: !40, baseType: !3, size: 64, align: 64, offset: 64)
+!44 = !DIDerivedType(tag: DW_TAG_pointer_type, name: "*const u8", baseType: !1, size: 64, align: 64, dwarfAddressSpace: 0)
+!45 = !{!46}
+!46 = !DITemplateTypeParameter(name: "T", type: !2)
+!50 = !DICompositeType(tag: DW_TAG_array_type, baseType: !51, size: 134217728, align: 8, elements: !52)
+!51 = !DICompositeType(tag: DW_TAG_array_type, baseType: !1, size: 32768, align: 8, elements: !52)
+!52 = !{!53}
+!53 = !DISubrange(count: 4096, lowerBound: 0)
+!55 = !DICompositeType(tag: DW_TAG_array_type, baseType: !2, align: 64, elements: !56)
+!56 = !{!57, !57}
+!57 = !DISubrange(count: 4294967296, lowerBound: 0)
+"#;
+
+    fn layout_of(id: &str) -> Layout{
+        let ir = LLVMIRMetadata::new(NODES).unwrap();
+        let mut ast = Ast{ inner: HashMap::new() };
+        ast.get_or_parse(id, &ir.metadata);
+        type_layout(&ast, id)
+    }
+
+    fn entry(offset: u64, size: u64, kind: LayoutKind) -> LayoutEntry{
+        LayoutEntry{ offset, size, kind }
+    }
+
+    #[test]
+    fn padding(){
+        let padded = layout_of("!10");
+        assert_eq!((padded.size, padded.align), (16, 8));
+        assert_eq!(padded.entries, vec![entry(0, 1, LayoutKind::Integer), entry(8, 8, LayoutKind::Float)]);
+        assert_eq!(padded.padding, vec![(1, 7)]);
+        assert!(padded.problems.is_empty(), "{:?}", padded.problems);
+    }
+
+    #[test]
+    fn mismatches_are_problems(){
+        let broken = layout_of("!20");
+        assert_eq!(broken.problems, [
+            "member unaligned of !20 is at bit 32, not aligned to 64 bits",
+            "!20 is 9 bytes, not a multiple of its alignment 8",
+            "8 bytes at 4 are past the end of the 9 bytes of !20",
+            "8 bytes at 16 are past the end of the 9 bytes of !20",
+        ]);
+    }
+
+    #[test]
+    fn recursive_pointees_stop(){
+        let reference = layout_of("!35");
+        let node = match reference.entries.as_slice(){
+            [LayoutEntry{ offset: 0, size: 8, kind: LayoutKind::Pointer(pointee) }] => match pointee.as_ref(){
+                Pointee::Layout(node) => node.clone(),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(node.entries, vec![
+            entry(0, 8, LayoutKind::Float),
+            entry(8, 8, LayoutKind::Pointer(Box::new(Pointee::Recursive("Node".to_string())))),
+        ]);
+    }
+
+    #[test]
+    fn raw_vec_buffers_point_to_their_elements(){
+        let raw_vec = layout_of("!40");
+        let element = Layout{ size: 8, align: 8, entries: vec![entry(0, 8, LayoutKind::Float)], padding: vec![], problems: vec![] };
+        assert_eq!(raw_vec.entries, vec![
+            entry(0, 8, LayoutKind::Pointer(Box::new(Pointee::Layout(element)))),
+            entry(8, 8, LayoutKind::Integer),
+        ]);
+        // only in a `RawVec`, a lone `*const u8` points to bytes
+        let bytes = layout_of("!44");
+        assert!(matches!(bytes.entries.as_slice(), [LayoutEntry{ kind: LayoutKind::Pointer(pointee), .. }]
+            if matches!(pointee.as_ref(), Pointee::Layout(byte) if byte.entries == vec![entry(0, 1, LayoutKind::Integer)])));
+    }
+
+    #[test]
+    fn arrays_stay_whole(){
+        // `[[u8; 4096]; 4096]`
+        let bytes = layout_of("!50");
+        let rows = match bytes.entries.as_slice(){
+            [LayoutEntry{ offset: 0, size: 16777216, kind: LayoutKind::Array(row, 4096) }] => row.clone(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(rows.entries, vec![entry(0, 4096, LayoutKind::Array(Box::new(layout_of("!1")), 4096))]);
+        assert!(bytes.padding.is_empty() && bytes.problems.is_empty(), "{:?}", bytes);
+        let overflowing = layout_of("!55");
+        assert!(overflowing.entries.is_empty());
+        assert!(overflowing.problems.contains(&"array !55 has more elements than fit in memory".to_string()), "{:?}", overflowing.problems);
+    }
+}
@@ -0,0 +1,314 @@
+//! Enzyme TypeTrees, such as `{[-1]:Pointer, [-1,0]:Float@double, [-1,8]:Integer}` for a pointer
+//! to a struct holding a `f64` and an `i64`.
+//!
+//! Each path gives the byte offset of a value, then the offset in the memory it points to and so on.
+//! `-1` stands for every offset: `[-1]` is the value itself and `[-1,-1]` every element it points to.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use crate::type_layout::{Layout, LayoutEntry, LayoutKind, Pointee};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConcreteType{
+    Anything,
+    Integer,
+    Pointer,
+    /// With the LLVM name of the float type, such as `double`
+    Float(String),
+}
+
+impl fmt::Display for ConcreteType{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            ConcreteType::Anything => write!(f, "Anything"),
+            ConcreteType::Integer => write!(f, "Integer"),
+            ConcreteType::Pointer => write!(f, "Pointer"),
+            ConcreteType::Float(float_type) => write!(f, "Float@{}", float_type),
+        }
+    }
+}
+
+impl FromStr for ConcreteType{
+    type Err = String;
+    fn from_str(concrete_type: &str) -> Result<Self, Self::Err> {
+        match concrete_type{
+            "Anything" => Ok(ConcreteType::Anything),
+            "Integer" => Ok(ConcreteType::Integer),
+            "Pointer" => Ok(ConcreteType::Pointer),
+            _ => match concrete_type.strip_prefix("Float@"){
+                Some(float_type) if !float_type.is_empty() => Ok(ConcreteType::Float(float_type.to_string())),
+                _ => Err(format!("unknown type `{}`", concrete_type)),
+            }
+        }
+    }
+}
+
+/// The LLVM type of a float of `size` bytes
+fn float_type(size: u64) -> String{
+    match size{
+        2 => "half".to_string(),
+        4 => "float".to_string(),
+        8 => "double".to_string(),
+        16 => "fp128".to_string(),
+        _ => format!("i{}", size * 8),
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TypeTree{
+    /// Ordered like Enzyme prints them
+    pub mapping: BTreeMap<Vec<i64>, ConcreteType>,
+}
+
+impl TypeTree{
+    /// The tree of a value laid out as `layout`.
+    /// Paths the variants of an enum disagree on are left out.
+    pub fn from_layout(layout: &Layout) -> Self{
+        let mut builder = TypeTreeBuilder{
+            tree: TypeTree::default(),
+            conflicts: vec![],
+        };
+        builder.insert_layout(&[], layout);
+        builder.tree
+    }
+
+    /// The tree of a pointer to a value of this tree
+    pub fn pointer_to(&self) -> Self{
+        let mut mapping = BTreeMap::new();
+        mapping.insert(vec![-1], ConcreteType::Pointer);
+        for (path, concrete_type) in &self.mapping{
+            mapping.insert([&[-1], path.as_slice()].concat(), concrete_type.clone());
+        }
+        TypeTree{ mapping }
+    }
+
+    /// The tree of the `size` bytes at `offset` of a value of this tree, alone in a register
+    pub fn fragment(&self, offset: u64, size: u64) -> Self{
+        let mut mapping = BTreeMap::new();
+        for (path, concrete_type) in &self.mapping{
+            let first = path[0];
+            let rest = &path[1..];
+            let first = match first{
+                -1 => -1,
+                // a scalar fills its register
+                _ if first as u64 == offset => -1,
+                _ if first as u64 > offset && (first as u64) < offset + size => first - offset as i64,
+                _ => continue,
+            };
+            mapping.insert([&[first], rest].concat(), concrete_type.clone());
+        }
+        TypeTree{ mapping }
+    }
+}
+
+/// Enzyme ignores the offsets of a type tree past its `-enzyme-max-type-offset`, 500 by default
+const MAX_OFFSET: u64 = 500;
+
+/// The scalar repeated over every byte of `layout`, like the elements of an array or a lone scalar,
+/// as its size and kind
+fn repeated_scalar(layout: &Layout) -> Option<(u64, &LayoutKind)>{
+    let mut repeated: Option<(u64, &LayoutKind)> = None;
+    let mut covered = 0;
+    for entry in &layout.entries{
+        let scalar = match &entry.kind{
+            LayoutKind::Array(element, _) => repeated_scalar(element)?,
+            kind => (entry.size, kind),
+        };
+        if entry.offset != covered || scalar.0 == 0 || repeated.is_some_and(|repeated| repeated != scalar){
+            return None;
+        }
+        repeated = Some(scalar);
+        covered += entry.size;
+    }
+    repeated.filter(|_| covered == layout.size)
+}
+
+struct TypeTreeBuilder{
+    tree: TypeTree,
+    conflicts: Vec<Vec<i64>>,
+}
+
+impl TypeTreeBuilder{
+    fn insert_layout(&mut self, prefix: &[i64], layout: &Layout){
+        if let Some((size, kind)) = repeated_scalar(layout){
+            return self.insert_scalar([prefix, &[-1]].concat(), size, kind);
+        }
+        self.insert_entries(prefix, 0, &layout.entries);
+    }
+
+    /// Inserts `entries` placed `offset` bytes into the value at `prefix`, arrays element by element
+    fn insert_entries(&mut self, prefix: &[i64], offset: u64, entries: &[LayoutEntry]){
+        for entry in entries{
+            let entry_offset = offset + entry.offset;
+            if entry_offset >= MAX_OFFSET{
+                return;
+            }
+            match &entry.kind{
+                LayoutKind::Array(element, length) => {
+                    for index in 0..*length{
+                        let element_offset = entry_offset + index * element.size;
+                        if element_offset >= MAX_OFFSET || element.size == 0{
+                            break;
+                        }
+                        self.insert_entries(prefix, element_offset, &element.entries);
+                    }
+                }
+                kind => self.insert_scalar([prefix, &[entry_offset as i64]].concat(), entry.size, kind),
+            }
+        }
+    }
+
+    fn insert_scalar(&mut self, path: Vec<i64>, size: u64, kind: &LayoutKind){
+        let concrete_type = match kind{
+            LayoutKind::Float => ConcreteType::Float(float_type(size)),
+            LayoutKind::Integer => ConcreteType::Integer,
+            LayoutKind::Pointer(_) => ConcreteType::Pointer,
+            LayoutKind::Array(..) => unreachable!("arrays are inserted element by element"),
+        };
+        if self.conflicts.contains(&path){
+            return;
+        }
+        match self.tree.mapping.get(&path){
+            Some(known) if *known != concrete_type => {
+                self.tree.mapping.remove(&path);
+                self.conflicts.push(path);
+                return;
+            }
+            Some(_) => {}
+            None => {
+                self.tree.mapping.insert(path.clone(), concrete_type);
+            }
+        }
+        if let LayoutKind::Pointer(pointee) = kind{
+            if let Pointee::Layout(layout) = pointee.as_ref(){
+                self.insert_layout(&path, layout);
+            }
+        }
+    }
+}
+
+impl fmt::Display for TypeTree{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self.mapping.iter().map(|(path, concrete_type)| {
+            let path: Vec<String> = path.iter().map(|offset| offset.to_string()).collect();
+            format!("[{}]:{}", path.join(","), concrete_type)
+        }).collect();
+        write!(f, "{{{}}}", entries.join(", "))
+    }
+}
+
+impl FromStr for TypeTree{
+    type Err = String;
+    /// Parses the trees Enzyme prints, such as `{[-1]:Pointer, [-1,0]:Float@double}`
+    fn from_str(tree: &str) -> Result<Self, Self::Err> {
+        let inner = tree.trim().strip_prefix('{').and_then(|tree| tree.strip_suffix('}'))
+            .ok_or_else(|| format!("type tree `{}` is not enclosed in braces", tree))?;
+        let mut mapping = BTreeMap::new();
+        for entry in inner.split(", ").filter(|entry| !entry.trim().is_empty()){
+            let (path, concrete_type) = entry.trim().strip_prefix('[')
+                .and_then(|entry| entry.split_once("]:"))
+                .ok_or_else(|| format!("expected `[offsets]:Type`, found `{}`", entry))?;
+            let path = path.split(',')
+                .map(|offset| offset.trim().parse::<i64>().map_err(|e| format!("invalid offset `{}` in `{}`: {}", offset, entry, e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            if mapping.insert(path, concrete_type.parse()?).is_some(){
+                return Err(format!("offsets of `{}` given twice", entry));
+            }
+        }
+        Ok(TypeTree{ mapping })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn entry(offset: u64, size: u64, kind: LayoutKind) -> LayoutEntry{
+        LayoutEntry{ offset, size, kind }
+    }
+
+    fn layout(size: u64, entries: Vec<LayoutEntry>) -> Layout{
+        Layout{ size, align: 8, entries, padding: vec![], problems: vec![] }
+    }
+
+    fn pointer_to(pointee: Layout) -> LayoutKind{
+        LayoutKind::Pointer(Box::new(Pointee::Layout(pointee)))
+    }
+
+    #[test]
+    fn pointer_to_struct(){
+        let pointee = layout(16, vec![entry(0, 8, LayoutKind::Float), entry(8, 8, LayoutKind::Integer)]);
+        let tree = TypeTree::from_layout(&layout(8, vec![entry(0, 8, pointer_to(pointee))]));
+        assert_eq!(tree.to_string(), "{[-1]:Pointer, [-1,0]:Float@double, [-1,8]:Integer}");
+    }
+
+    #[test]
+    fn arrays_and_scalars_cover_every_offset(){
+        let array = layout(16, vec![entry(0, 4, LayoutKind::Float), entry(4, 4, LayoutKind::Float), entry(8, 4, LayoutKind::Float), entry(12, 4, LayoutKind::Float)]);
+        let slice = layout(16, vec![entry(0, 8, pointer_to(array)), entry(8, 8, LayoutKind::Integer)]);
+        let tree = TypeTree::from_layout(&slice);
+        assert_eq!(tree.to_string(), "{[0]:Pointer, [0,-1]:Float@float, [8]:Integer}");
+    }
+
+    #[test]
+    fn conflicting_variants_are_left_out(){
+        // `Result<u32, f32>`: the tag, then either payload at the same offset
+        let result = layout(8, vec![entry(0, 4, LayoutKind::Integer), entry(4, 4, LayoutKind::Integer), entry(4, 4, LayoutKind::Float)]);
+        assert_eq!(TypeTree::from_layout(&result).to_string(), "{[0]:Integer}");
+    }
+
+    #[test]
+    fn recursive_pointees_stop(){
+        let node = layout(16, vec![entry(0, 8, LayoutKind::Float), entry(8, 8, LayoutKind::Pointer(Box::new(Pointee::Recursive("Node".to_string()))))]);
+        let tree = TypeTree::from_layout(&layout(8, vec![entry(0, 8, pointer_to(node))]));
+        assert_eq!(tree.to_string(), "{[-1]:Pointer, [-1,0]:Float@double, [-1,8]:Pointer}");
+    }
+
+    #[test]
+    fn arrays_are_not_expanded_past_the_max_offset(){
+        let floats = layout(8, vec![entry(0, 4, LayoutKind::Float), entry(4, 4, LayoutKind::Float)]);
+        let matrix = layout(16, vec![entry(0, 16, LayoutKind::Array(Box::new(floats), 2))]);
+        assert_eq!(TypeTree::from_layout(&layout(8, vec![entry(0, 8, pointer_to(matrix))])).to_string(), "{[-1]:Pointer, [-1,-1]:Float@float}");
+
+        // `(usize, [f64; 1 << 20])`
+        let double = layout(8, vec![entry(0, 8, LayoutKind::Float)]);
+        let large = layout(8 + (8 << 20), vec![entry(0, 8, LayoutKind::Integer), entry(8, 8 << 20, LayoutKind::Array(Box::new(double), 1 << 20))]);
+        let tree = TypeTree::from_layout(&large);
+        assert_eq!(tree.mapping.len(), 63);
+        assert_eq!(tree.mapping.keys().last(), Some(&vec![496]));
+    }
+
+    #[test]
+    fn pointers_and_fragments(){
+        let slice: TypeTree = "{[0]:Pointer, [0,-1]:Float@double, [8]:Integer}".parse().unwrap();
+        assert_eq!(slice.fragment(0, 8).to_string(), "{[-1]:Pointer, [-1,-1]:Float@double}");
+        assert_eq!(slice.fragment(8, 8).to_string(), "{[-1]:Integer}");
+        assert_eq!(slice.pointer_to().to_string(), "{[-1]:Pointer, [-1,0]:Pointer, [-1,0,-1]:Float@double, [-1,8]:Integer}");
+    }
+
+    #[test]
+    fn round_trip(){
+        for tree in [
+            "{}",
+            "{[-1]:Float@double}",
+            "{[-1]:Pointer, [-1,0]:Float@double, [-1,8]:Integer}",
+            "{[-1]:Pointer, [-1,-1]:Anything, [-1,0]:Pointer, [-1,0,-1]:Float@float}",
+        ]{
+            let parsed: TypeTree = tree.parse().unwrap();
+            assert_eq!(parsed.to_string(), tree);
+        }
+    }
+
+    #[test]
+    fn parsing_orders_like_enzyme(){
+        let tree: TypeTree = "{[8]:Integer, [0]:Pointer, [-1]:Anything}".parse().unwrap();
+        assert_eq!(tree.to_string(), "{[-1]:Anything, [0]:Pointer, [8]:Integer}");
+    }
+
+    #[test]
+    fn invalid_trees(){
+        for tree in ["[-1]:Pointer", "{[-1]Pointer}", "{[x]:Pointer}", "{[-1]:Float@}", "{[-1]:Pointer, [-1]:Integer}"]{
+            assert!(tree.parse::<TypeTree>().is_err(), "{}", tree);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Runs the examples through `cargo enzyme`, see tests/softmax_alloc.rs
+use std::path::Path;
+use std::process::Command;
+
+/// Builds and runs `examples/{example}.rs` with `cargo enzyme`, returns what it printed
+pub fn run_example(example: &str) -> String{
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let has_plugin = std::fs::read_dir(manifest_dir).unwrap()
+        .any(|entry| entry.unwrap().file_name().to_string_lossy().starts_with("LLVMEnzyme-"));
+    assert!(
+        std::env::var_os("ENZYME_PLUGIN").is_some() || has_plugin,
+        "needs Enzyme: set ENZYME_PLUGIN or put the LLVMEnzyme plugin of your `opt` in {}", manifest_dir.display()
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_cargo-enzyme"))
+        .args(["enzyme", "--example", example, "--run", "--manifest-path"])
+        .arg(manifest_dir.join("Cargo.toml"))
+        .current_dir(manifest_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(output.status.success(), "{}\n{}", stdout, String::from_utf8_lossy(&output.stderr));
+    stdout
+}
+
+/// The values of lines such as `d weighted_sum / d x_0:   0.123456 expected   0.123456`
+pub fn printed_gradient(stdout: &str, prefix: &str) -> Vec<f64>{
+    stdout.lines()
+        .filter_map(|line| line.strip_prefix(prefix))
+        .map(|line| line.split_once(':').unwrap().1.split_whitespace().next().unwrap().parse().unwrap())
+        .collect()
+}
+
+pub fn assert_close(found: &[f64], expected: &[f64], stdout: &str){
+    assert_eq!(found.len(), expected.len(), "{}", stdout);
+    for (j, (found, expected)) in found.iter().zip(expected).enumerate(){
+        assert!((found - expected).abs() < 1e-6, "d/dx_{}: {} instead of {}", j, found, expected);
+    }
+}
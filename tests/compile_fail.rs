@@ -0,0 +1,9 @@
+//! Misuses of `autodiff!`, `fwddiff!` and `#[autodiff]` that must not compile, the expected errors
+//! are next to each case in tests/ui. `TRYBUILD=overwrite cargo test --test compile_fail` updates them.
+//! Only the errors with our own message belong here: the wording of rustc's errors changes between
+//! releases, so the type, arity and width checks are `compile_fail` doctests matching the error code.
+#[test]
+fn compile_fail(){
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,22 @@
+//! Builds `examples/softmax_alloc.rs` with `cargo enzyme` and checks the gradient it prints. Needs
+//! Enzyme and its `opt`, see `cargo enzyme --help`:
+//! `ENZYME_PLUGIN=/path/to/LLVMEnzyme-19.so cargo test --test softmax_alloc -- --ignored`
+mod common;
+
+const INPUT: [f64; 4] = [0.5, -1., 2., 0.25];
+const WEIGHTS: [f64; 4] = [1., -2., 0.5, 3.];
+
+/// d/dx_j sum_i w_i s_i = s_j (w_j - sum_i w_i s_i) with s the softmax of the input
+fn expected_gradient() -> Vec<f64>{
+    let sum: f64 = INPUT.iter().map(|x| x.exp()).sum();
+    let softmax: Vec<f64> = INPUT.iter().map(|x| x.exp() / sum).collect();
+    let mean: f64 = softmax.iter().zip(&WEIGHTS).map(|(out, weight)| out * weight).sum();
+    softmax.iter().zip(&WEIGHTS).map(|(out, weight)| out * (weight - mean)).collect()
+}
+
+#[test]
+#[ignore]
+fn gradient_through_an_allocation(){
+    let stdout = common::run_example("softmax_alloc");
+    common::assert_close(&common::printed_gradient(&stdout, "d weighted_sum / d x_"), &expected_gradient(), &stdout);
+}
@@ -0,0 +1,16 @@
+//! Builds `examples/to_vec.rs` with `cargo enzyme` and checks the gradient it prints, the `to_vec`
+//! of `dummy_nn_with_loss` in src/main.rs used to crash Enzyme:
+//! `ENZYME_PLUGIN=/path/to/LLVMEnzyme-19.so cargo test --test to_vec -- --ignored`
+mod common;
+
+const INPUT: [f64; 4] = [0.5, -1., 2., 0.25];
+const WEIGHTS: [f64; 4] = [1., -2., 0.5, 3.];
+
+#[test]
+#[ignore]
+fn gradient_through_to_vec(){
+    let stdout = common::run_example("to_vec");
+    // d/dx_j sum_i w_i x_i^2 = 2 w_j x_j
+    let expected: Vec<f64> = INPUT.iter().zip(&WEIGHTS).map(|(x, weight)| 2. * weight * x).collect();
+    common::assert_close(&common::printed_gradient(&stdout, "d loss / d x_"), &expected, &stdout);
+}
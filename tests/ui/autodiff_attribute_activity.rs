@@ -0,0 +1,8 @@
+use oxide_enzyme_macros::autodiff;
+
+#[autodiff(grad_sum, Mutable)]
+fn sum(input: &[f64; 4]) -> f64{
+    input.iter().sum()
+}
+
+fn main(){}
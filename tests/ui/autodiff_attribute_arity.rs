@@ -0,0 +1,8 @@
+use oxide_enzyme_macros::autodiff;
+
+#[autodiff(grad_product, Active)]
+fn product(x: f64, y: f64) -> f64{
+    x * y
+}
+
+fn main(){}
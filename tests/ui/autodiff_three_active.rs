@@ -0,0 +1,9 @@
+use oxide_enzyme::autodiff;
+
+fn product(x: f64, y: f64, z: f64) -> f64{
+    x * y * z
+}
+
+fn main(){
+    autodiff!(product, Active(1.), Active(2.), Active(3.));
+}
@@ -0,0 +1,9 @@
+use oxide_enzyme::fwddiff;
+
+fn square(x: f64) -> f64{
+    x * x
+}
+
+fn main(){
+    let _: f64 = fwddiff!(square, Active(3.));
+}